@@ -0,0 +1,188 @@
+//! Runs the simulation for a fixed number of ticks without a window or graphics context,
+//! optionally driven by an input script, and prints the resulting state.
+//!
+//! Usage: headless [TICKS] [--seed SEED] [--script PATH] [--map PATH] [--record PATH]
+//!                 [--replay PATH]
+//!
+//! With --record, the run is written to a replay file. With --replay, the seed and inputs
+//! come from a replay file instead, TICKS defaults to the length of the replay, and the
+//! physics checksum after each tick is compared against the one recorded. The first tick
+//! which doesn't match is reported and the exit status is non-zero.
+
+extern crate belt;
+
+use std::env;
+use std::fs;
+use std::process;
+
+use belt::game_state::GameState;
+use belt::input::{InputModel, ScriptedInput};
+use belt::map::{self, MapFile};
+use belt::replay::{self, Replay, ReplayRecorder};
+use belt::sprite_atlas::SpriteAtlas;
+
+const USAGE: &str = "Usage: headless [TICKS] [--seed SEED] [--script PATH] [--map PATH] \
+                     [--record PATH] [--replay PATH]";
+
+struct Args {
+    num_ticks: Option<u64>,
+    seed: Option<u64>,
+    script_path: Option<String>,
+    /// The built-in map is used if this isn't given
+    map_path: Option<String>,
+    record_path: Option<String>,
+    replay_path: Option<String>,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = env::args().skip(1);
+    let mut num_ticks = None;
+    let mut seed = None;
+    let mut script_path = None;
+    let mut map_path = None;
+    let mut record_path = None;
+    let mut replay_path = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--seed" => {
+                seed = Some(
+                    args.next()
+                        .ok_or("missing SEED")?
+                        .parse()
+                        .map_err(|_| "SEED must be a non-negative integer")?,
+                );
+            }
+            "--script" => script_path = Some(args.next().ok_or("missing PATH")?),
+            "--map" => map_path = Some(args.next().ok_or("missing PATH")?),
+            "--record" => record_path = Some(args.next().ok_or("missing PATH")?),
+            "--replay" => replay_path = Some(args.next().ok_or("missing PATH")?),
+            other if num_ticks.is_none() && !other.starts_with("--") => {
+                num_ticks = Some(
+                    other
+                        .parse()
+                        .map_err(|_| "TICKS must be a non-negative integer")?,
+                );
+            }
+            other => return Err(format!("unexpected argument \"{}\"", other)),
+        }
+    }
+    if replay_path.is_some() {
+        if seed.is_some() {
+            return Err("--seed can't be used with --replay".to_string());
+        }
+        if script_path.is_some() {
+            return Err("--script can't be used with --replay".to_string());
+        }
+    } else if num_ticks.is_none() {
+        return Err("missing TICKS".to_string());
+    }
+    Ok(Args {
+        num_ticks,
+        seed,
+        script_path,
+        map_path,
+        record_path,
+        replay_path,
+    })
+}
+
+fn main() {
+    let args = parse_args().unwrap_or_else(|message| {
+        eprintln!("{}\n{}", message, USAGE);
+        process::exit(1);
+    });
+
+    let mut script = args.script_path.as_ref().map(|path| {
+        let contents = fs::read_to_string(path).unwrap_or_else(|error| {
+            eprintln!("Failed to read {}: {}", path, error);
+            process::exit(1);
+        });
+        ScriptedInput::parse(&contents).unwrap_or_else(|error| {
+            eprintln!("Failed to parse {}: {}", path, error);
+            process::exit(1);
+        })
+    });
+
+    let map_image = match args.map_path.as_ref() {
+        Some(path) => MapFile::new(path).load(),
+        None => map::load_builtin(),
+    }
+    .unwrap_or_else(|error| {
+        let map_name = args
+            .map_path
+            .as_ref()
+            .map_or("(built-in)", |path| path.as_str());
+        eprintln!("Failed to load map {}: {}", map_name, error);
+        process::exit(1);
+    });
+
+    let replay = args.replay_path.as_ref().map(|path| {
+        let replay = Replay::load(path).unwrap_or_else(|error| {
+            eprintln!("Failed to load replay {}: {}", path, error);
+            process::exit(1);
+        });
+        if replay.map_hash() != replay::map_hash(&map_image) {
+            eprintln!("Warning: replay {} was recorded on a different map", path);
+        }
+        replay
+    });
+    let seed = replay
+        .as_ref()
+        .map_or_else(|| args.seed.unwrap_or(0), Replay::seed);
+    let num_ticks = args
+        .num_ticks
+        .or_else(|| replay.as_ref().map(Replay::num_ticks))
+        .expect("no tick count");
+
+    let mut recorder = args.record_path.as_ref().map(|path| {
+        ReplayRecorder::create(path, seed, replay::map_hash(&map_image)).unwrap_or_else(
+            |error| {
+                eprintln!("Failed to create replay {}: {}", path, error);
+                process::exit(1);
+            },
+        )
+    });
+
+    let sprite_atlas = SpriteAtlas::builtin().unwrap_or_else(|error| {
+        eprintln!("Failed to load sprite atlas: {}", error);
+        process::exit(1);
+    });
+    let mut game_state = GameState::new(&map_image, &sprite_atlas, seed);
+    let mut input_model = InputModel::default();
+    let mut first_desync = None;
+    for tick in 0..num_ticks {
+        if let Some(script) = script.as_mut() {
+            script.apply(tick, &mut input_model);
+        }
+        let result = replay::run_tick(
+            tick,
+            &mut game_state,
+            &mut input_model,
+            replay.as_ref(),
+            recorder.as_mut(),
+        );
+        if result.desynced && first_desync.is_none() {
+            first_desync = Some(tick);
+        }
+        if let Err(error) = result.record_result {
+            eprintln!("Failed to write replay: {}", error);
+            process::exit(1);
+        }
+    }
+    if let Some(recorder) = recorder {
+        recorder.finish().unwrap_or_else(|error| {
+            eprintln!("Failed to write replay: {}", error);
+            process::exit(1);
+        });
+    }
+
+    let player_info = game_state.player_info(1.);
+    println!("ticks: {}", num_ticks);
+    println!("entities: {}", game_state.num_entities());
+    println!("player: {:?}", player_info.physics);
+    println!("checksum: {:016x}", game_state.physics_checksum());
+    if let Some(tick) = first_desync {
+        eprintln!("Replay desynced at tick {}", tick);
+        process::exit(1);
+    }
+}
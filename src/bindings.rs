@@ -0,0 +1,454 @@
+use gilrs::ev::{Axis, Button};
+use glutin::VirtualKeyCode;
+use toml;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use belt::input::InputModel;
+
+const DEFAULT_BINDINGS: &str = include_str!("default_bindings.toml");
+
+/// Gamepads whose name doesn't match any profile use this one
+const DEFAULT_GAMEPAD_PROFILE: &str = "default";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ButtonAction {
+    AimUp,
+    AimDown,
+    AimLeft,
+    AimRight,
+    Shoot,
+    Thrust,
+    ReverseThrust,
+    StrafeLeft,
+    StrafeRight,
+    ToggleAutoBrake,
+    ZoomIn,
+    ZoomOut,
+}
+
+/// The order in which actions are prompted for while rebinding
+const BUTTON_ACTIONS: [ButtonAction; 12] = [
+    ButtonAction::AimUp,
+    ButtonAction::AimDown,
+    ButtonAction::AimLeft,
+    ButtonAction::AimRight,
+    ButtonAction::Shoot,
+    ButtonAction::Thrust,
+    ButtonAction::ReverseThrust,
+    ButtonAction::StrafeLeft,
+    ButtonAction::StrafeRight,
+    ButtonAction::ToggleAutoBrake,
+    ButtonAction::ZoomIn,
+    ButtonAction::ZoomOut,
+];
+
+impl ButtonAction {
+    /// `value` is how far the button is pressed, from 0 to 1
+    fn apply(self, value: f32, input_model: &mut InputModel) {
+        match self {
+            ButtonAction::AimUp => input_model.set_aim_y(-value),
+            ButtonAction::AimDown => input_model.set_aim_y(value),
+            ButtonAction::AimLeft => input_model.set_aim_x(-value),
+            ButtonAction::AimRight => input_model.set_aim_x(value),
+            ButtonAction::Shoot => {
+                if value > 0.5 {
+                    input_model.press_shoot()
+                } else {
+                    input_model.release_shoot()
+                }
+            }
+            ButtonAction::Thrust => input_model.set_thrust(value),
+            ButtonAction::ReverseThrust => input_model.set_thrust(-value),
+            ButtonAction::StrafeLeft => input_model.set_strafe(-value),
+            ButtonAction::StrafeRight => input_model.set_strafe(value),
+            ButtonAction::ToggleAutoBrake => {
+                if value > 0.5 {
+                    input_model.press_auto_brake()
+                } else {
+                    input_model.release_auto_brake()
+                }
+            }
+            ButtonAction::ZoomIn => input_model.set_zoom(value),
+            ButtonAction::ZoomOut => input_model.set_zoom(-value),
+        }
+    }
+}
+
+impl fmt::Display for ButtonAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ButtonAction::AimUp => "aim up",
+            ButtonAction::AimDown => "aim down",
+            ButtonAction::AimLeft => "aim left",
+            ButtonAction::AimRight => "aim right",
+            ButtonAction::Shoot => "shoot",
+            ButtonAction::Thrust => "thrust",
+            ButtonAction::ReverseThrust => "reverse thrust",
+            ButtonAction::StrafeLeft => "strafe left",
+            ButtonAction::StrafeRight => "strafe right",
+            ButtonAction::ToggleAutoBrake => "toggle auto-brake",
+            ButtonAction::ZoomIn => "zoom in",
+            ButtonAction::ZoomOut => "zoom out",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AxisAction {
+    AimX,
+    AimY,
+    Thrust,
+    Strafe,
+    Zoom,
+}
+
+fn default_scale() -> f32 {
+    1.
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct AxisBinding {
+    action: AxisAction,
+    #[serde(default = "default_scale")]
+    scale: f32,
+    #[serde(default)]
+    offset: f32,
+}
+
+impl AxisBinding {
+    fn apply(&self, value: f32, input_model: &mut InputModel) {
+        let value = value * self.scale + self.offset;
+        match self.action {
+            AxisAction::AimX => input_model.set_aim_x(value),
+            AxisAction::AimY => input_model.set_aim_y(value),
+            AxisAction::Thrust => input_model.set_thrust(value),
+            AxisAction::Strafe => input_model.set_strafe(value),
+            AxisAction::Zoom => input_model.set_zoom(value),
+        }
+    }
+}
+
+macro_rules! names {
+    ($ty:ident: $($variant:ident),* $(,)*) => {
+        &[$((stringify!($variant), $ty::$variant)),*]
+    };
+}
+
+const KEY_NAMES: &[(&str, VirtualKeyCode)] = names!(VirtualKeyCode:
+    Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9, Key0, A, B, C, D, E, F, G, H, I,
+    J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z, Escape, F1, F2, F3, F4, F5, F6, F7,
+    F8, F9, F10, F11, F12, F13, F14, F15, Snapshot, Scroll, Pause, Insert, Home, Delete,
+    End, PageDown, PageUp, Left, Up, Right, Down, Back, Return, Space, Compose, Caret,
+    Numlock, Numpad0, Numpad1, Numpad2, Numpad3, Numpad4, Numpad5, Numpad6, Numpad7,
+    Numpad8, Numpad9, AbntC1, AbntC2, Add, Apostrophe, Apps, At, Ax, Backslash, Calculator,
+    Capital, Colon, Comma, Convert, Decimal, Divide, Equals, Grave, Kana, Kanji, LAlt,
+    LBracket, LControl, LMenu, LShift, LWin, Mail, MediaSelect, MediaStop, Minus, Multiply,
+    Mute, MyComputer, NavigateForward, NavigateBackward, NextTrack, NoConvert, NumpadComma,
+    NumpadEnter, NumpadEquals, OEM102, Period, PlayPause, Power, PrevTrack, RAlt, RBracket,
+    RControl, RMenu, RShift, RWin, Semicolon, Slash, Sleep, Stop, Subtract, Sysrq, Tab,
+    Underline, Unlabeled, VolumeDown, VolumeUp, Wake, WebBack, WebFavorites, WebForward,
+    WebHome, WebRefresh, WebSearch, WebStop, Yen,
+);
+
+const BUTTON_NAMES: &[(&str, Button)] = names!(Button:
+    South, East, North, West, C, Z, LeftTrigger, LeftTrigger2, RightTrigger, RightTrigger2,
+    Select, Start, Mode, LeftThumb, RightThumb, DPadUp, DPadDown, DPadLeft, DPadRight,
+    Unknown,
+);
+
+const AXIS_NAMES: &[(&str, Axis)] = names!(Axis:
+    LeftStickX, LeftStickY, LeftZ, RightStickX, RightStickY, RightZ, DPadX, DPadY, Unknown,
+);
+
+fn from_name<T: Copy>(names: &[(&str, T)], name: &str) -> Option<T> {
+    names
+        .iter()
+        .find(|&&(n, _)| n == name)
+        .map(|&(_, value)| value)
+}
+
+fn to_name<T: Copy + PartialEq>(names: &[(&'static str, T)], value: T) -> &'static str {
+    names
+        .iter()
+        .find(|&&(_, v)| v == value)
+        .map(|&(name, _)| name)
+        .expect("missing name")
+}
+
+#[derive(Debug)]
+pub enum BindingsError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    Serialize(toml::ser::Error),
+    UnknownName { kind: &'static str, name: String },
+}
+
+impl fmt::Display for BindingsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BindingsError::Io(error) => write!(f, "{}", error),
+            BindingsError::Parse(error) => write!(f, "{}", error),
+            BindingsError::Serialize(error) => write!(f, "{}", error),
+            BindingsError::UnknownName { kind, name } => {
+                write!(f, "unknown {} \"{}\"", kind, name)
+            }
+        }
+    }
+}
+
+/// The format of the bindings file, before names are resolved
+#[derive(Serialize, Deserialize)]
+struct BindingsFile {
+    keyboard: BTreeMap<String, ButtonAction>,
+    gamepads: BTreeMap<String, GamepadProfileFile>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct GamepadProfileFile {
+    #[serde(default)]
+    buttons: BTreeMap<String, ButtonAction>,
+    #[serde(default)]
+    axes: BTreeMap<String, AxisBinding>,
+}
+
+#[derive(Clone, Default)]
+struct GamepadProfile {
+    buttons: Vec<(Button, ButtonAction)>,
+    axes: Vec<(Axis, AxisBinding)>,
+}
+
+/// Maps keyboard keys, and gamepad buttons and axes, to actions on an `InputModel`.
+pub struct Bindings {
+    keyboard: Vec<(VirtualKeyCode, ButtonAction)>,
+    /// Keyed by gamepad name
+    gamepads: BTreeMap<String, GamepadProfile>,
+}
+
+fn resolve<T: Copy, U: Copy>(
+    names: &[(&str, T)],
+    kind: &'static str,
+    bindings: &BTreeMap<String, U>,
+) -> Result<Vec<(T, U)>, BindingsError> {
+    bindings
+        .iter()
+        .map(|(name, &binding)| {
+            from_name(names, name)
+                .map(|input| (input, binding))
+                .ok_or_else(|| BindingsError::UnknownName {
+                    kind,
+                    name: name.clone(),
+                })
+        })
+        .collect()
+}
+
+impl Bindings {
+    pub fn parse(contents: &str) -> Result<Self, BindingsError> {
+        let file: BindingsFile =
+            toml::from_str(contents).map_err(BindingsError::Parse)?;
+        let keyboard = resolve(KEY_NAMES, "key", &file.keyboard)?;
+        let mut gamepads = BTreeMap::new();
+        for (name, profile) in file.gamepads.iter() {
+            let profile = GamepadProfile {
+                buttons: resolve(BUTTON_NAMES, "button", &profile.buttons)?,
+                axes: resolve(AXIS_NAMES, "axis", &profile.axes)?,
+            };
+            gamepads.insert(name.clone(), profile);
+        }
+        Ok(Self { keyboard, gamepads })
+    }
+
+    /// Loads bindings from `path`, or the default bindings if it doesn't exist.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, BindingsError> {
+        match fs::read_to_string(path) {
+            Ok(contents) => Self::parse(&contents),
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => {
+                Self::parse(DEFAULT_BINDINGS)
+            }
+            Err(error) => Err(BindingsError::Io(error)),
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), BindingsError> {
+        let file = BindingsFile {
+            keyboard: self
+                .keyboard
+                .iter()
+                .map(|&(key, action)| (to_name(KEY_NAMES, key).to_string(), action))
+                .collect(),
+            gamepads: self
+                .gamepads
+                .iter()
+                .map(|(name, profile)| {
+                    let profile = GamepadProfileFile {
+                        buttons: profile
+                            .buttons
+                            .iter()
+                            .map(|&(button, action)| {
+                                (to_name(BUTTON_NAMES, button).to_string(), action)
+                            })
+                            .collect(),
+                        axes: profile
+                            .axes
+                            .iter()
+                            .map(|&(axis, binding)| {
+                                (to_name(AXIS_NAMES, axis).to_string(), binding)
+                            })
+                            .collect(),
+                    };
+                    (name.clone(), profile)
+                })
+                .collect(),
+        };
+        let contents = toml::to_string(&file).map_err(BindingsError::Serialize)?;
+        fs::write(path, contents).map_err(BindingsError::Io)
+    }
+
+    fn gamepad_profile(&self, gamepad_name: &str) -> Option<&GamepadProfile> {
+        self.gamepads
+            .get(gamepad_name)
+            .or_else(|| self.gamepads.get(DEFAULT_GAMEPAD_PROFILE))
+    }
+
+    /// Returns the profile for the named gamepad, creating it as a copy of the default
+    /// profile if it doesn't exist.
+    fn gamepad_profile_mut(&mut self, gamepad_name: &str) -> &mut GamepadProfile {
+        if !self.gamepads.contains_key(gamepad_name) {
+            let profile = self
+                .gamepads
+                .get(DEFAULT_GAMEPAD_PROFILE)
+                .cloned()
+                .unwrap_or_default();
+            self.gamepads.insert(gamepad_name.to_string(), profile);
+        }
+        self.gamepads.get_mut(gamepad_name).unwrap()
+    }
+
+    pub fn key(&self, key: VirtualKeyCode, pressed: bool, input_model: &mut InputModel) {
+        let value = if pressed { 1. } else { 0. };
+        for &(_, action) in self.keyboard.iter().filter(|&&(k, _)| k == key) {
+            action.apply(value, input_model);
+        }
+    }
+
+    /// `value` is how far the button is pressed, from 0 to 1
+    pub fn gamepad_button(
+        &self,
+        gamepad_name: &str,
+        button: Button,
+        value: f32,
+        input_model: &mut InputModel,
+    ) {
+        if let Some(profile) = self.gamepad_profile(gamepad_name) {
+            for &(_, action) in profile.buttons.iter().filter(|&&(b, _)| b == button) {
+                action.apply(value, input_model);
+            }
+        }
+    }
+
+    pub fn gamepad_axis(
+        &self,
+        gamepad_name: &str,
+        axis: Axis,
+        value: f32,
+        input_model: &mut InputModel,
+    ) {
+        if let Some(profile) = self.gamepad_profile(gamepad_name) {
+            for &(_, binding) in profile.axes.iter().filter(|&&(a, _)| a == axis) {
+                binding.apply(value, input_model);
+            }
+        }
+    }
+}
+
+/// Steps through each button action in turn, binding it to the next key or gamepad button
+/// pressed in place of its existing bindings on that device. Axes can only be bound in the
+/// bindings file.
+pub struct Rebinding {
+    action_index: usize,
+}
+
+/// Starts rebinding. This key can't itself be rebound.
+pub const REBIND_KEY: VirtualKeyCode = VirtualKeyCode::F1;
+
+/// Pressing this key while rebinding leaves the current action's bindings unchanged
+pub const SKIP_KEY: VirtualKeyCode = VirtualKeyCode::Escape;
+
+impl Rebinding {
+    pub fn new() -> Self {
+        Self { action_index: 0 }
+    }
+
+    fn action(&self) -> ButtonAction {
+        BUTTON_ACTIONS[self.action_index]
+    }
+
+    pub fn prompt(&self) -> String {
+        format!(
+            "Press a key or button to {} ({} to skip)",
+            self.action(),
+            to_name(KEY_NAMES, SKIP_KEY)
+        )
+    }
+
+    /// Returns true once every action has been bound or skipped
+    fn advance(&mut self) -> bool {
+        self.action_index += 1;
+        self.action_index == BUTTON_ACTIONS.len()
+    }
+
+    /// Binds `key` to the current action and moves on to the next, unless it's the rebind
+    /// key, which is ignored. Returns true once every action has been bound or skipped.
+    pub fn key(&mut self, key: VirtualKeyCode, bindings: &mut Bindings) -> bool {
+        if key == REBIND_KEY {
+            return false;
+        }
+        if key != SKIP_KEY {
+            let action = self.action();
+            bindings.keyboard.retain(|&(k, a)| k != key && a != action);
+            bindings.keyboard.push((key, action));
+        }
+        self.advance()
+    }
+
+    pub fn gamepad_button(
+        &mut self,
+        gamepad_name: &str,
+        button: Button,
+        bindings: &mut Bindings,
+    ) -> bool {
+        let action = self.action();
+        let profile = bindings.gamepad_profile_mut(gamepad_name);
+        profile.buttons.retain(|&(b, a)| b != button && a != action);
+        profile.buttons.push((button, action));
+        self.advance()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rebinding_ignores_the_rebind_key() {
+        let mut bindings = Bindings::parse(DEFAULT_BINDINGS).unwrap();
+        let mut rebinding = Rebinding::new();
+        assert!(!rebinding.key(REBIND_KEY, &mut bindings));
+        assert!(bindings.keyboard.iter().all(|&(key, _)| key != REBIND_KEY));
+        assert_eq!(rebinding.action(), BUTTON_ACTIONS[0]);
+
+        rebinding.key(VirtualKeyCode::Q, &mut bindings);
+        assert!(bindings
+            .keyboard
+            .contains(&(VirtualKeyCode::Q, BUTTON_ACTIONS[0])));
+        assert_eq!(rebinding.action(), BUTTON_ACTIONS[1]);
+    }
+}
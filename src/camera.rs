@@ -0,0 +1,136 @@
+use cgmath::{InnerSpace, Vector2, vec2};
+use rand::prng::XorShiftRng;
+use rand::{Rng, SeedableRng};
+
+use game_state::TICK_SECONDS;
+use input::InputModel;
+
+pub const MIN_ZOOM: f32 = 1.;
+pub const MAX_ZOOM: f32 = 8.;
+const INITIAL_ZOOM: f32 = 4.;
+
+/// Factor by which the zoom changes per second while the zoom control is fully held
+const ZOOM_RATE: f32 = 4.;
+
+/// Fraction of the distance to its target the camera has left to cover after one second
+const FOLLOW_REMAINING_AFTER_ONE_SECOND: f32 = 0.002;
+
+/// The camera leads its target by where the target will be this far in the future
+const LOOK_AHEAD_SECONDS: f32 = 0.25;
+const MAX_LOOK_AHEAD_IN_PIXELS: f32 = 64.;
+
+/// However the camera lags or leads, the target stays within this fraction of the distance
+/// from the centre of the screen to its edge
+const MAX_TARGET_OFFSET: f32 = 0.5;
+
+/// Trauma lost per second. Shake is proportional to the square of trauma, so it dies away
+/// quickly at first and then lingers gently.
+const TRAUMA_DECAY: f32 = 1.5;
+const MAX_SHAKE_IN_PIXELS: f32 = 8.;
+
+/// What the renderer needs to know about the camera to draw a frame.
+#[derive(Debug, Clone, Copy)]
+pub struct CameraView {
+    pub centre: Vector2<f32>,
+    pub zoom: f32,
+}
+
+/// Follows a target around the world, smoothly and slightly ahead of where it's moving,
+/// without showing anything beyond the edges of the world. Advanced once per tick, and
+/// interpolated between ticks like entities are.
+pub struct Camera {
+    centre: Vector2<f32>,
+    previous_centre: Vector2<f32>,
+    zoom: f32,
+    previous_zoom: f32,
+    window_size: Vector2<f32>,
+    /// Between 0 and 1. Determines the strength of screen shake.
+    trauma: f32,
+    shake_offset: Vector2<f32>,
+    rng: XorShiftRng,
+}
+
+impl Camera {
+    pub fn new(centre: Vector2<f32>, window_size: Vector2<f32>) -> Self {
+        Self {
+            centre,
+            previous_centre: centre,
+            zoom: INITIAL_ZOOM,
+            previous_zoom: INITIAL_ZOOM,
+            window_size,
+            trauma: 0.,
+            shake_offset: vec2(0., 0.),
+            rng: XorShiftRng::seed_from_u64(0),
+        }
+    }
+
+    pub fn set_window_size(&mut self, window_size: Vector2<f32>) {
+        self.window_size = window_size;
+    }
+
+    /// Shakes the screen. Trauma from successive calls accumulates, up to a limit.
+    pub fn add_trauma(&mut self, amount: f32) {
+        self.trauma = (self.trauma + amount).min(1.);
+    }
+
+    pub fn update(
+        &mut self,
+        target: Vector2<f32>,
+        target_velocity: Vector2<f32>,
+        world_size: Vector2<f32>,
+        input_model: &InputModel,
+    ) {
+        self.previous_centre = self.centre;
+        self.previous_zoom = self.zoom;
+
+        self.zoom = (self.zoom * ZOOM_RATE.powf(input_model.zoom() * TICK_SECONDS))
+            .clamp(MIN_ZOOM, MAX_ZOOM);
+
+        let mut look_ahead = target_velocity * LOOK_AHEAD_SECONDS;
+        if look_ahead.magnitude() > MAX_LOOK_AHEAD_IN_PIXELS {
+            look_ahead = look_ahead.normalize_to(MAX_LOOK_AHEAD_IN_PIXELS);
+        }
+        let follow = 1. - FOLLOW_REMAINING_AFTER_ONE_SECOND.powf(TICK_SECONDS);
+        self.centre += (target + look_ahead - self.centre) * follow;
+
+        let half_visible_size = self.window_size / (self.zoom * 2.);
+        let max_offset = half_visible_size * MAX_TARGET_OFFSET;
+        self.centre = vec2(
+            self.centre
+                .x
+                .max(target.x - max_offset.x)
+                .min(target.x + max_offset.x),
+            self.centre
+                .y
+                .max(target.y - max_offset.y)
+                .min(target.y + max_offset.y),
+        );
+        let clamp = |centre: f32, half_visible_size: f32, world_size: f32| {
+            if half_visible_size * 2. >= world_size {
+                world_size / 2.
+            } else {
+                centre
+                    .max(half_visible_size)
+                    .min(world_size - half_visible_size)
+            }
+        };
+        self.centre = vec2(
+            clamp(self.centre.x, half_visible_size.x, world_size.x),
+            clamp(self.centre.y, half_visible_size.y, world_size.y),
+        );
+
+        self.trauma = (self.trauma - TRAUMA_DECAY * TICK_SECONDS).max(0.);
+        let shake = self.trauma * self.trauma * MAX_SHAKE_IN_PIXELS;
+        self.shake_offset =
+            vec2(self.rng.gen_range(-1., 1.), self.rng.gen_range(-1., 1.)) * shake;
+    }
+
+    pub fn view(&self, alpha: f32) -> CameraView {
+        CameraView {
+            centre: self.previous_centre
+                + (self.centre - self.previous_centre) * alpha
+                + self.shake_offset,
+            zoom: self.previous_zoom + (self.zoom - self.previous_zoom) * alpha,
+        }
+    }
+}
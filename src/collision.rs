@@ -0,0 +1,499 @@
+use cgmath::{InnerSpace, Vector2, vec2};
+use fnv::FnvHashMap;
+use image::RgbaImage;
+
+use visibility::is_transparent;
+
+/// Entities never move further than this many pixels between collision checks, so fast
+/// entities can't tunnel through thin walls.
+const MAX_STEP_IN_PIXELS: f32 = 4.;
+
+/// The size of the cells `overlapping_pairs` sorts shapes into. Shapes much larger than this
+/// are slower to sort, and shapes much smaller are compared with more of their neighbours.
+const BROADPHASE_CELL_SIZE: f32 = 128.;
+
+/// How an entity reacts when its movement would take it into an opaque part of the map.
+#[derive(Debug, Clone, Copy)]
+pub enum CollisionResponse {
+    /// Remain at the last non-colliding position and lose all velocity.
+    Stop,
+    /// Cancel the component of velocity pointing into the wall and continue moving along it.
+    Slide,
+    /// Reflect the component of velocity pointing into the wall, scaled by `restitution`.
+    Bounce { restitution: f32 },
+}
+
+impl CollisionResponse {
+    /// The fraction of speed kept in the direction of a collision with another entity
+    pub fn restitution(&self) -> f32 {
+        match *self {
+            CollisionResponse::Stop | CollisionResponse::Slide => 0.,
+            CollisionResponse::Bounce { restitution } => restitution,
+        }
+    }
+}
+
+/// Where two overlapping shapes meet. `normal` is the unit vector along which the second
+/// shape must be moved by `depth` to separate them.
+#[derive(Debug, Clone, Copy)]
+pub struct Contact {
+    pub normal: Vector2<f32>,
+    pub depth: f32,
+}
+
+/// A rectangle centred at `centre`, with its height axis aligned with `facing`. This matches
+/// the way the quad shader positions sprites.
+#[derive(Debug, Clone, Copy)]
+pub struct OrientedBox {
+    pub centre: Vector2<f32>,
+    pub dimensions: Vector2<f32>,
+    pub facing: Vector2<f32>,
+}
+
+impl OrientedBox {
+    fn right(&self) -> Vector2<f32> {
+        vec2(-self.facing.y, self.facing.x)
+    }
+
+    fn with_centre(&self, centre: Vector2<f32>) -> Self {
+        Self { centre, ..*self }
+    }
+
+    pub fn contains(&self, point: Vector2<f32>) -> bool {
+        let offset = point - self.centre;
+        offset.dot(self.right()).abs() <= self.dimensions.x / 2.
+            && offset.dot(self.facing).abs() <= self.dimensions.y / 2.
+    }
+
+    /// The axis-aligned box just containing the line from `start` to `end`
+    pub fn around_segment(start: Vector2<f32>, end: Vector2<f32>) -> Self {
+        Self {
+            centre: (start + end) / 2.,
+            dimensions: vec2((end.x - start.x).abs(), (end.y - start.y).abs()),
+            facing: vec2(0., 1.),
+        }
+    }
+
+    /// Returns how far along the line from `start` to `end` it first touches this box, as a
+    /// fraction of the line's length, or `None` if it misses. Lines starting inside the box
+    /// touch it at 0.
+    pub fn segment_entry(&self, start: Vector2<f32>, end: Vector2<f32>) -> Option<f32> {
+        let offset = start - self.centre;
+        let delta = end - start;
+        let (mut entry, mut exit) = (0f32, 1f32);
+        for &(axis, half_size) in &[
+            (self.right(), self.dimensions.x / 2.),
+            (self.facing, self.dimensions.y / 2.),
+        ] {
+            let position = offset.dot(axis);
+            let speed = delta.dot(axis);
+            if speed == 0. {
+                if position.abs() > half_size {
+                    return None;
+                }
+                continue;
+            }
+            let near = (-half_size - position) / speed;
+            let far = (half_size - position) / speed;
+            entry = entry.max(near.min(far));
+            exit = exit.min(near.max(far));
+            if entry > exit {
+                return None;
+            }
+        }
+        Some(entry)
+    }
+
+    /// Half the width and height of the smallest axis-aligned box containing this one
+    fn half_extents(&self) -> Vector2<f32> {
+        let right = self.right();
+        vec2(
+            (right.x.abs() * self.dimensions.x + self.facing.x.abs() * self.dimensions.y)
+                / 2.,
+            (right.y.abs() * self.dimensions.x + self.facing.y.abs() * self.dimensions.y)
+                / 2.,
+        )
+    }
+
+    /// Returns the shallowest way to separate `other` from this box, if they overlap. Uses
+    /// the separating axis theorem, for which the only axes to test are the boxes' own.
+    pub fn overlap(&self, other: &OrientedBox) -> Option<Contact> {
+        let offset = other.centre - self.centre;
+        let mut contact: Option<Contact> = None;
+        for &axis in &[self.facing, self.right(), other.facing, other.right()] {
+            let extent = |shape: &OrientedBox| {
+                (shape.right().dot(axis).abs() * shape.dimensions.x
+                    + shape.facing.dot(axis).abs() * shape.dimensions.y)
+                    / 2.
+            };
+            let distance = offset.dot(axis);
+            let depth = extent(self) + extent(other) - distance.abs();
+            if depth <= 0. {
+                return None;
+            }
+            if contact.is_none_or(|contact| depth < contact.depth) {
+                let normal = if distance < 0. { -axis } else { axis };
+                contact = Some(Contact { normal, depth });
+            }
+        }
+        contact
+    }
+}
+
+/// Returns the indices of every pair of `shapes` whose bounding boxes overlap, each with the
+/// lower index first, in ascending order. Shapes are sorted into a grid first so only those
+/// sharing a cell are compared, which keeps this fast for large numbers of small shapes.
+pub fn overlapping_pairs(shapes: &[OrientedBox]) -> Vec<(usize, usize)> {
+    let bounds = shapes
+        .iter()
+        .map(|shape| {
+            let half_extents = shape.half_extents();
+            (shape.centre - half_extents, shape.centre + half_extents)
+        })
+        .collect::<Vec<_>>();
+    let cell_of = |position: Vector2<f32>| {
+        (
+            (position.x / BROADPHASE_CELL_SIZE).floor() as i32,
+            (position.y / BROADPHASE_CELL_SIZE).floor() as i32,
+        )
+    };
+    let mut cells: FnvHashMap<(i32, i32), Vec<usize>> = FnvHashMap::default();
+    for (index, &(min, max)) in bounds.iter().enumerate() {
+        let (min_cell, max_cell) = (cell_of(min), cell_of(max));
+        for y in min_cell.1..=max_cell.1 {
+            for x in min_cell.0..=max_cell.0 {
+                cells.entry((x, y)).or_default().push(index);
+            }
+        }
+    }
+    let mut pairs = Vec::new();
+    for (&cell, indices) in cells.iter() {
+        for (i, &a) in indices.iter().enumerate() {
+            for &b in &indices[i + 1..] {
+                let ((min_a, max_a), (min_b, max_b)) = (bounds[a], bounds[b]);
+                if min_a.x > max_b.x
+                    || min_b.x > max_a.x
+                    || min_a.y > max_b.y
+                    || min_b.y > max_a.y
+                {
+                    continue;
+                }
+                // Shapes sharing several cells are only paired in the first one, which is
+                // the cell containing the top-left corner of their bounding boxes' overlap
+                let overlap_min = vec2(min_a.x.max(min_b.x), min_a.y.max(min_b.y));
+                if cell_of(overlap_min) == cell {
+                    pairs.push((a.min(b), a.max(b)));
+                }
+            }
+        }
+    }
+    pairs.sort();
+    pairs
+}
+
+/// CPU-side copy of the opaque/transparent classification of the map, in world pixels.
+/// Everything outside the map is considered opaque.
+pub struct CollisionMap {
+    width: u32,
+    height: u32,
+    /// Summed-area table of opaque pixels with an extra leading row and column of zeros, so
+    /// the number of opaque pixels in any rectangle can be found in constant time.
+    opaque_count: Vec<u32>,
+}
+
+impl CollisionMap {
+    pub fn from_image(image: &RgbaImage) -> Self {
+        let (width, height) = image.dimensions();
+        let stride = width as usize + 1;
+        let mut opaque_count = vec![0; stride * (height as usize + 1)];
+        for y in 0..height {
+            let mut row_count = 0;
+            for x in 0..width {
+                if !is_transparent(image.get_pixel(x, y)) {
+                    row_count += 1;
+                }
+                let index = (y as usize + 1) * stride + x as usize + 1;
+                opaque_count[index] = opaque_count[index - stride] + row_count;
+            }
+        }
+        Self {
+            width,
+            height,
+            opaque_count,
+        }
+    }
+
+    pub fn size(&self) -> Vector2<f32> {
+        vec2(self.width as f32, self.height as f32)
+    }
+
+    fn count_opaque(&self, x0: u32, y0: u32, x1: u32, y1: u32) -> u32 {
+        let stride = self.width as usize + 1;
+        let at = |x: u32, y: u32| self.opaque_count[y as usize * stride + x as usize];
+        at(x1, y1) + at(x0, y0) - at(x0, y1) - at(x1, y0)
+    }
+
+    fn is_opaque_pixel(&self, x: u32, y: u32) -> bool {
+        self.count_opaque(x, y, x + 1, y + 1) != 0
+    }
+
+    fn is_opaque(&self, position: Vector2<f32>) -> bool {
+        if position.x < 0. || position.y < 0. {
+            return true;
+        }
+        let (x, y) = (position.x as u32, position.y as u32);
+        x >= self.width || y >= self.height || self.is_opaque_pixel(x, y)
+    }
+
+    /// Returns true if the straight line from `start` to `end` passes through an opaque
+    /// part of the map.
+    pub fn line_collides(&self, start: Vector2<f32>, end: Vector2<f32>) -> bool {
+        let delta = end - start;
+        let num_steps = delta.magnitude().ceil().max(1.);
+        (0..=num_steps as usize)
+            .any(|i| self.is_opaque(start + delta * (i as f32 / num_steps)))
+    }
+
+    pub fn collides(&self, shape: &OrientedBox) -> bool {
+        let half_extents = shape.half_extents();
+        let min = shape.centre - half_extents;
+        let max = shape.centre + half_extents;
+        if min.x < 0.
+            || min.y < 0.
+            || max.x > self.width as f32
+            || max.y > self.height as f32
+        {
+            return true;
+        }
+        let (x0, y0) = (min.x as u32, min.y as u32);
+        let (x1, y1) = (max.x.ceil() as u32, max.y.ceil() as u32);
+        if self.count_opaque(x0, y0, x1, y1) == 0 {
+            return false;
+        }
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let pixel_centre = vec2(x as f32 + 0.5, y as f32 + 0.5);
+                if self.is_opaque_pixel(x, y) && shape.contains(pixel_centre) {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    /// Moves `shape` by `displacement`, reacting to any collisions along the way according
+    /// to `response`. Returns the new centre and the displacement after the response, which
+    /// is proportional to the entity's new velocity. Shapes which already overlap the map
+    /// are moved freely so they have a chance to escape.
+    pub fn resolve_movement(
+        &self,
+        shape: &OrientedBox,
+        displacement: Vector2<f32>,
+        response: CollisionResponse,
+    ) -> (Vector2<f32>, Vector2<f32>) {
+        if self.collides(shape) {
+            return (shape.centre + displacement, displacement);
+        }
+        let collides_at = |centre| self.collides(&shape.with_centre(centre));
+        let num_steps = (displacement.magnitude() / MAX_STEP_IN_PIXELS)
+            .ceil()
+            .max(1.);
+        let mut centre = shape.centre;
+        let mut displacement = displacement;
+        for _ in 0..num_steps as usize {
+            let step = displacement / num_steps;
+            let next_centre = centre + step;
+            if !collides_at(next_centre) {
+                centre = next_centre;
+                continue;
+            }
+            let x_only = centre + vec2(step.x, 0.);
+            let y_only = centre + vec2(0., step.y);
+            match response {
+                CollisionResponse::Stop => {
+                    displacement = vec2(0., 0.);
+                    break;
+                }
+                CollisionResponse::Slide => {
+                    if step.x != 0. && !collides_at(x_only) {
+                        centre = x_only;
+                        displacement.y = 0.;
+                    } else if step.y != 0. && !collides_at(y_only) {
+                        centre = y_only;
+                        displacement.x = 0.;
+                    } else {
+                        displacement = vec2(0., 0.);
+                        break;
+                    }
+                }
+                CollisionResponse::Bounce { restitution } => {
+                    let blocked_x = collides_at(x_only);
+                    let blocked_y = collides_at(y_only);
+                    if blocked_x == blocked_y {
+                        displacement = -displacement * restitution;
+                    } else if blocked_x {
+                        displacement.x = -displacement.x * restitution;
+                    } else {
+                        displacement.y = -displacement.y * restitution;
+                    }
+                    break;
+                }
+            }
+        }
+        (centre, displacement)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    const SQRT_HALF: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+    fn square(centre: Vector2<f32>, size: f32, facing: Vector2<f32>) -> OrientedBox {
+        OrientedBox {
+            centre,
+            dimensions: vec2(size, size),
+            facing,
+        }
+    }
+
+    fn assert_close(actual: Vector2<f32>, expected: Vector2<f32>) {
+        assert!(
+            (actual - expected).magnitude() < 1e-4,
+            "expected {:?}, got {:?}",
+            expected,
+            actual
+        );
+    }
+
+    fn assert_contact(contact: Option<Contact>, normal: Vector2<f32>, depth: f32) {
+        let contact = contact.expect("no contact");
+        assert_close(contact.normal, normal);
+        assert!(
+            (contact.depth - depth).abs() < 1e-4,
+            "expected depth {}, got {}",
+            depth,
+            contact.depth
+        );
+    }
+
+    #[test]
+    fn overlap_normal_points_from_the_first_box_to_the_second() {
+        let up = vec2(0., 1.);
+        let a = square(vec2(0., 0.), 10., up);
+        let b = square(vec2(8., 0.), 10., up);
+        assert_contact(a.overlap(&b), vec2(1., 0.), 2.);
+        assert_contact(b.overlap(&a), vec2(-1., 0.), 2.);
+        let c = square(vec2(1., -7.), 10., up);
+        assert_contact(a.overlap(&c), vec2(0., -1.), 3.);
+    }
+
+    #[test]
+    fn overlap_of_rotated_boxes() {
+        let diagonal = vec2(SQRT_HALF, SQRT_HALF);
+        // A corner of the rotated box, half its diagonal from its centre, pokes into the side
+        // of the upright one
+        let upright = square(vec2(0., 0.), 10., vec2(0., 1.));
+        let rotated = square(vec2(10., 0.), 10., diagonal);
+        assert_contact(
+            upright.overlap(&rotated),
+            vec2(1., 0.),
+            5. + 10. * SQRT_HALF - 10.,
+        );
+        // Boxes rotated the same way separate along their own axes
+        let a = square(vec2(0., 0.), 10., diagonal);
+        let b = square(diagonal * 8., 10., diagonal);
+        assert_contact(a.overlap(&b), diagonal, 2.);
+    }
+
+    #[test]
+    fn touching_edges_are_not_a_contact() {
+        let up = vec2(0., 1.);
+        let a = square(vec2(0., 0.), 10., up);
+        assert!(a.overlap(&square(vec2(10., 3.), 10., up)).is_none());
+        assert!(a.overlap(&square(vec2(-2., -10.), 10., up)).is_none());
+        assert!(a.overlap(&square(vec2(10., 10.), 10., up)).is_none());
+    }
+
+    #[test]
+    fn pair_spanning_several_cells_is_reported_once() {
+        let up = vec2(0., 1.);
+        let shapes = [
+            square(vec2(-20., 100.), 300., up),
+            square(vec2(150., 150.), 300., up),
+            square(vec2(128., 128.), 10., up),
+            square(vec2(1000., 1000.), 10., up),
+        ];
+        assert_eq!(overlapping_pairs(&shapes), vec![(0, 1), (0, 2), (1, 2)]);
+    }
+
+    #[test]
+    fn segment_entry() {
+        let upright = square(vec2(0., 0.), 10., vec2(0., 1.));
+        let entry = upright.segment_entry(vec2(-15., 0.), vec2(5., 0.));
+        assert!((entry.unwrap() - 0.5).abs() < 1e-4);
+        assert_eq!(upright.segment_entry(vec2(1., 1.), vec2(20., 1.)), Some(0.));
+        assert!(upright
+            .segment_entry(vec2(-15., 6.), vec2(15., 6.))
+            .is_none());
+        assert!(upright
+            .segment_entry(vec2(-15., 0.), vec2(-6., 0.))
+            .is_none());
+        // Stops short of the side of the upright box, but reaches a corner of the rotated one
+        let rotated = square(vec2(0., 0.), 10., vec2(SQRT_HALF, SQRT_HALF));
+        assert!(rotated
+            .segment_entry(vec2(-15., 0.), vec2(-6., 0.))
+            .is_some());
+    }
+
+    /// A 32 pixel square map with opaque pixels from x = 20 onwards
+    fn map_with_wall() -> CollisionMap {
+        let mut image = RgbaImage::from_pixel(32, 32, Rgba([255, 255, 255, 255]));
+        for y in 0..32 {
+            for x in 20..32 {
+                image.put_pixel(x, y, Rgba([0, 0, 0, 255]));
+            }
+        }
+        CollisionMap::from_image(&image)
+    }
+
+    fn small_box() -> OrientedBox {
+        square(vec2(10., 10.), 4., vec2(0., 1.))
+    }
+
+    #[test]
+    fn stop_response_halts_against_the_wall() {
+        let (centre, displacement) = map_with_wall().resolve_movement(
+            &small_box(),
+            vec2(20., 0.),
+            CollisionResponse::Stop,
+        );
+        assert_close(centre, vec2(18., 10.));
+        assert_close(displacement, vec2(0., 0.));
+    }
+
+    #[test]
+    fn slide_response_keeps_moving_along_the_wall() {
+        let (centre, displacement) = map_with_wall().resolve_movement(
+            &small_box(),
+            vec2(20., 8.),
+            CollisionResponse::Slide,
+        );
+        assert!(centre.x <= 18.);
+        assert!((centre.y - 18.).abs() < 1e-4);
+        assert_close(displacement, vec2(0., 8.));
+    }
+
+    #[test]
+    fn bounce_response_reflects_off_the_wall() {
+        let (centre, displacement) = map_with_wall().resolve_movement(
+            &small_box(),
+            vec2(20., 0.),
+            CollisionResponse::Bounce { restitution: 0.5 },
+        );
+        assert_close(centre, vec2(18., 10.));
+        assert_close(displacement, vec2(-10., 0.));
+    }
+}
@@ -0,0 +1,159 @@
+use cgmath::{InnerSpace, Vector2, vec2};
+use image::{self, GrayImage, ImageError, Luma};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use game_state::GameState;
+use visibility::VisionCone;
+
+/// Width and height of the square part of the world each cell of an `ExploredMap` covers.
+/// This matches the coarsest level of detail the lighting shader traces rays through.
+pub const EXPLORED_CELL_SIZE: f32 = 16.;
+
+const FULLY_EXPLORED: u8 = 255;
+
+#[derive(Debug)]
+pub enum ExploredMapError {
+    Io(io::Error),
+    Decode(ImageError),
+    WrongSize {
+        expected: (u32, u32),
+        actual: (u32, u32),
+    },
+}
+
+impl fmt::Display for ExploredMapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExploredMapError::Io(error) => write!(f, "{}", error),
+            ExploredMapError::Decode(error) => {
+                write!(f, "failed to decode image: {}", error)
+            }
+            ExploredMapError::WrongSize { expected, actual } => write!(
+                f,
+                "expected {}x{} cells but found {}x{}, so it's for a different map",
+                expected.0, expected.1, actual.0, actual.1
+            ),
+        }
+    }
+}
+
+fn size_in_cells(world_size: Vector2<f32>) -> (u32, u32) {
+    (
+        (world_size.x / EXPLORED_CELL_SIZE).ceil() as u32,
+        (world_size.y / EXPLORED_CELL_SIZE).ceil() as u32,
+    )
+}
+
+/// Remembers which parts of the world the player has seen. Each cell records how clearly
+/// its centre has ever been seen, from 0 if never to 255 if in plain view. It's stored as
+/// a greyscale image, one pixel per cell.
+pub struct ExploredMap {
+    cells: GrayImage,
+}
+
+impl ExploredMap {
+    /// Creates a map of a world of the given size with nothing yet explored.
+    pub fn new(world_size: Vector2<f32>) -> Self {
+        let (width, height) = size_in_cells(world_size);
+        Self {
+            cells: GrayImage::new(width, height),
+        }
+    }
+
+    /// Loads a map saved with `save`, checking that it's for a world of the given size.
+    pub fn load<P: AsRef<Path>>(
+        path: P,
+        world_size: Vector2<f32>,
+    ) -> Result<Self, ExploredMapError> {
+        let bytes = fs::read(path).map_err(ExploredMapError::Io)?;
+        let cells = image::load_from_memory(&bytes)
+            .map_err(ExploredMapError::Decode)?
+            .to_luma();
+        let expected = size_in_cells(world_size);
+        if cells.dimensions() != expected {
+            return Err(ExploredMapError::WrongSize {
+                expected,
+                actual: cells.dimensions(),
+            });
+        }
+        Ok(Self { cells })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), ExploredMapError> {
+        self.cells.save(path).map_err(ExploredMapError::Io)
+    }
+
+    pub fn width(&self) -> u32 {
+        self.cells.width()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.cells.height()
+    }
+
+    /// One byte per cell, in rows from the top of the world down
+    pub fn cells(&self) -> &[u8] {
+        &self.cells
+    }
+
+    /// The size of the area covered by the map, which may extend slightly beyond the
+    /// world if the world's size isn't a multiple of `EXPLORED_CELL_SIZE`.
+    pub fn size_in_pixels(&self) -> Vector2<f32> {
+        vec2(self.width() as f32, self.height() as f32) * EXPLORED_CELL_SIZE
+    }
+
+    /// Records what the player can currently see within `max_distance` of their position,
+    /// using the same tests as the lighting shader. Returns true if anything was seen more
+    /// clearly than before. Cells already fully explored are skipped, so once the player's
+    /// surroundings are explored this is cheap.
+    ///
+    /// This deliberately differs from what the shader draws in two ways. Only the map blocks
+    /// line of sight, not occluding sprites, since they move on and what's behind them
+    /// would otherwise stay unexplored after they're gone. And the game only calls this
+    /// every 6 ticks, so anything glimpsed for less than that may be missed.
+    pub fn update(
+        &mut self,
+        game_state: &GameState,
+        vision_cone: Option<&VisionCone>,
+        max_distance: f32,
+    ) -> bool {
+        let player_physics = game_state.player_info(1.).physics;
+        let eye = player_physics.centre_position;
+        let cell_range = |centre: f32, size: u32| {
+            let min = ((centre - max_distance) / EXPLORED_CELL_SIZE)
+                .floor()
+                .max(0.) as u32;
+            let max = (((centre + max_distance) / EXPLORED_CELL_SIZE)
+                .ceil()
+                .max(0.) as u32)
+                .min(size);
+            min..max
+        };
+        let mut changed = false;
+        for y in cell_range(eye.y, self.height()) {
+            for x in cell_range(eye.x, self.width()) {
+                let explored = self.cells.get_pixel(x, y).data[0];
+                if explored == FULLY_EXPLORED {
+                    continue;
+                }
+                let centre =
+                    (vec2(x as f32, y as f32) + vec2(0.5, 0.5)) * EXPLORED_CELL_SIZE;
+                if (centre - eye).magnitude() > max_distance {
+                    continue;
+                }
+                let visibility = vision_cone.map_or(1., |cone| {
+                    cone.visibility(eye, player_physics.facing, centre)
+                });
+                let seen = (visibility * FULLY_EXPLORED as f32).round() as u8;
+                if seen > explored && game_state.is_visible_from_player(centre) {
+                    self.cells.put_pixel(x, y, Luma { data: [seen] });
+                    changed = true;
+                }
+            }
+        }
+        changed
+    }
+}
@@ -0,0 +1,1332 @@
+use cgmath::{InnerSpace, Vector2, vec2};
+use fnv::{FnvHashMap, FnvHasher};
+use image::RgbaImage;
+use rand::prng::XorShiftRng;
+use rand::{Rng, SeedableRng};
+use std::hash::Hasher;
+
+use collision::{self, CollisionMap, CollisionResponse, OrientedBox};
+use input::InputModel;
+use sprite_atlas::{Animation, SpriteAtlas, SpriteRect};
+use visibility::VisibilityMap;
+
+pub const TICKS_PER_SECOND: u32 = 60;
+pub const TICK_SECONDS: f32 = 1. / TICKS_PER_SECOND as f32;
+
+/// Identifies an entity. Indices are reused once an entity is despawned, so each reuse
+/// gets a new generation, which stops IDs kept from before it refer to the new entity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+struct EntityId {
+    index: u32,
+    generation: u32,
+}
+
+#[derive(Default)]
+struct EntityIdAllocator {
+    /// The current generation of each index handed out so far
+    generations: Vec<u32>,
+    free_indices: Vec<u32>,
+}
+
+impl EntityIdAllocator {
+    fn allocate(&mut self) -> EntityId {
+        match self.free_indices.pop() {
+            Some(index) => EntityId {
+                index,
+                generation: self.generations[index as usize],
+            },
+            None => {
+                self.generations.push(0);
+                EntityId {
+                    index: self.generations.len() as u32 - 1,
+                    generation: 0,
+                }
+            }
+        }
+    }
+    /// Makes `id` stale and its index free for reuse. Returns false if it was already stale.
+    fn free(&mut self, id: EntityId) -> bool {
+        if !self.is_live(id) {
+            return false;
+        }
+        let generation = &mut self.generations[id.index as usize];
+        *generation = generation.wrapping_add(1);
+        self.free_indices.push(id.index);
+        true
+    }
+    fn is_live(&self, id: EntityId) -> bool {
+        self.generations.get(id.index as usize) == Some(&id.generation)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Physics {
+    pub centre_position: Vector2<f32>,
+    pub bounding_dimensions: Vector2<f32>,
+    pub velocity: Vector2<f32>,
+    pub facing: Vector2<f32>,
+    /// Radians per second, with positive values turning `facing` from the x axis towards
+    /// the y axis
+    pub angular_velocity: f32,
+    /// Angular velocity is kept within this many radians per second either way
+    pub max_angular_speed: f32,
+    /// The rate at which angular velocity decays exponentially, per second
+    pub angular_damping: f32,
+    /// Determines how entities push each other when they collide. Only entities with a
+    /// `CollisionResponse` collide with each other, and those must have a positive mass.
+    pub mass: f32,
+}
+
+impl Physics {
+    /// Blend between `previous` and `self` for rendering between ticks, where `alpha` is
+    /// the fraction of a tick which has elapsed since `self` was computed.
+    fn interpolate(&self, previous: &Physics, alpha: f32) -> Physics {
+        let facing = previous.facing.lerp(self.facing, alpha);
+        Physics {
+            centre_position: previous.centre_position.lerp(self.centre_position, alpha),
+            facing: if facing.magnitude2() > 0.001 {
+                facing.normalize()
+            } else {
+                self.facing
+            },
+            ..*self
+        }
+    }
+    fn oriented_box(&self) -> OrientedBox {
+        OrientedBox {
+            centre: self.centre_position,
+            dimensions: self.bounding_dimensions,
+            facing: self.facing,
+        }
+    }
+}
+
+/// Which pass an entity's sprite is drawn in, and so what it's drawn on top of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderLayer {
+    /// Part of the world, so lit and shadowed along with the map, and hidden when out of
+    /// the player's sight.
+    World,
+    /// Drawn unlit over the world after lighting, so always visible at full brightness.
+    Overlay,
+    /// Drawn over everything else at the window's resolution rather than the world's, and
+    /// positioned in window pixels from its top-left corner rather than in the world, so it
+    /// stays put as the camera moves and zooms.
+    Ui,
+}
+
+#[derive(Clone)]
+pub struct Graphics {
+    pub sprite: SpriteRect,
+    pub layer: RenderLayer,
+    /// If set, the mostly-opaque parts of the sprite block light and line of sight like
+    /// walls do. This only affects rendering. `GameState::is_visible_from_player` still
+    /// sees through entities. Only sprites in `RenderLayer::World` can occlude.
+    pub occludes: bool,
+}
+
+/// A point light which moves with its entity. Everything within `radius` pixels of the
+/// light which it has a clear line to is lit by `colour` multiplied by `intensity`.
+#[derive(Debug, Clone, Copy)]
+pub struct Light {
+    pub colour: [f32; 3],
+    pub radius: f32,
+    pub intensity: f32,
+}
+
+#[derive(Clone)]
+struct Weapon {
+    projectile_graphics: Graphics,
+    projectile_light: Light,
+    projectile_dimensions: Vector2<f32>,
+    projectile_speed: f32,
+    projectile_lifetime: f32,
+    projectile_damage: f32,
+    reload_time: f32,
+    time_until_reloaded: f32,
+}
+
+impl Weapon {
+    fn blaster(projectile_sprite: SpriteRect) -> Self {
+        Self {
+            projectile_graphics: Graphics {
+                sprite: projectile_sprite,
+                layer: RenderLayer::Overlay,
+                occludes: false,
+            },
+            projectile_light: Light {
+                colour: [1., 0.4, 0.2],
+                radius: 64.,
+                intensity: 0.5,
+            },
+            projectile_dimensions: vec2(8., 20.),
+            projectile_speed: 480.,
+            projectile_lifetime: 1.5,
+            projectile_damage: 10.,
+            reload_time: 0.125,
+            time_until_reloaded: 0.,
+        }
+    }
+}
+
+/// How a ship responds to the pilot's controls. Accelerations are in pixels per second per
+/// second.
+#[derive(Debug, Clone, Copy)]
+struct FlightModel {
+    thrust_acceleration: f32,
+    reverse_acceleration: f32,
+    strafe_acceleration: f32,
+    /// The rate at which velocity decays exponentially, per second
+    linear_drag: f32,
+    /// Pixels per second
+    max_speed: f32,
+    /// Whether the ship can fire its thrusters to cancel drift when the pilot turns on
+    /// auto-brake
+    has_auto_brake: bool,
+}
+
+impl FlightModel {
+    fn fighter() -> Self {
+        Self {
+            thrust_acceleration: 720.,
+            reverse_acceleration: 360.,
+            strafe_acceleration: 360.,
+            linear_drag: 0.3,
+            max_speed: 600.,
+            has_auto_brake: true,
+        }
+    }
+}
+
+struct Projectile {
+    time_remaining: f32,
+    damage: f32,
+    /// The entity which fired the projectile, which it can't hit
+    owner: EntityId,
+}
+
+/// Entities with health are destroyed when it runs out
+struct Health {
+    current: f32,
+}
+
+/// Steps through an animation, showing each frame in turn in the entity's `Graphics`
+struct Animated {
+    animation: Animation,
+    seconds: f32,
+}
+
+/// A light which fades out over its lifetime, then disappears along with its entity
+struct Flash {
+    time_remaining: f32,
+    duration: f32,
+    initial_intensity: f32,
+}
+
+/// The sprites and animations entities are created with, looked up from the atlas once
+struct Sprites {
+    player: SpriteRect,
+    projectile: SpriteRect,
+    asteroid: SpriteRect,
+    thruster_flame: Animation,
+    explosion: Animation,
+}
+
+impl Sprites {
+    fn from_atlas(atlas: &SpriteAtlas) -> Self {
+        let sprite = |name| {
+            atlas
+                .sprite(name)
+                .unwrap_or_else(|| panic!("sprite atlas has no sprite \"{}\"", name))
+        };
+        let animation = |name| {
+            atlas
+                .animation(name)
+                .cloned()
+                .unwrap_or_else(|| panic!("sprite atlas has no animation \"{}\"", name))
+        };
+        Self {
+            player: sprite("player"),
+            projectile: sprite("projectile"),
+            asteroid: sprite("asteroid"),
+            thruster_flame: animation("thruster-flame"),
+            explosion: animation("explosion"),
+        }
+    }
+}
+
+/// Drawn behind the player while thrusting
+const THRUSTER_FLAME_DIMENSIONS: Vector2<f32> = Vector2 { x: 12., y: 24. };
+
+/// The size of the explosion when an asteroid is hit, in multiples of the asteroid's size
+const EXPLOSION_SCALE: f32 = 1.5;
+
+const PLAYER_LIGHT: Light = Light {
+    colour: [1., 1., 1.],
+    radius: 768.,
+    intensity: 1.,
+};
+
+const ASTEROID_HIT_FLASH_COLOUR: [f32; 3] = [1., 0.7, 0.3];
+const ASTEROID_HIT_FLASH_SECONDS: f32 = 0.5;
+/// The radius of the flash from an asteroid hit, in multiples of the asteroid's size
+const ASTEROID_HIT_FLASH_RADIUS_SCALE: f32 = 4.;
+
+const PLAYER_MASS: f32 = 2.;
+/// Radians per second
+const PLAYER_MAX_ANGULAR_SPEED: f32 = 6.;
+/// Radians per second per second
+const PLAYER_ANGULAR_ACCELERATION: f32 = 40.;
+/// How quickly the player tries to turn towards where they're aiming, in radians per
+/// second for each radian they're facing away from it
+const PLAYER_TURN_RESPONSIVENESS: f32 = 12.;
+const PLAYER_ANGULAR_DAMPING: f32 = 4.;
+/// Radians per second
+const MAX_ASTEROID_ANGULAR_SPEED: f32 = 2.;
+/// Mass per square pixel, so an asteroid the size of the player weighs the same
+const ASTEROID_DENSITY: f32 = 1. / 1024.;
+
+const PLAYER_SPAWN_POSITION: Vector2<f32> = Vector2 { x: 266., y: 550. };
+const PLAYER_HEALTH: f32 = 100.;
+/// How long after the player is destroyed before they reappear at the spawn position
+const PLAYER_RESPAWN_SECONDS: f32 = 3.;
+const ASTEROID_HEALTH_PER_PIXEL: f32 = 1. / 4.;
+
+/// Entities which collide take damage for each pixel per second by which the collision
+/// changes their velocity, beyond this free allowance
+const MIN_DAMAGING_SPEED_CHANGE: f32 = 60.;
+const COLLISION_DAMAGE_PER_SPEED_CHANGE: f32 = 1. / 10.;
+
+const NUM_ASTEROIDS: usize = 12;
+const ASTEROID_SIZE: f32 = 64.;
+const MIN_ASTEROID_SIZE: f32 = 16.;
+const MIN_ASTEROID_DISTANCE_FROM_PLAYER: f32 = 200.;
+
+struct Asteroid;
+
+fn random_unit_vector<R: Rng>(rng: &mut R) -> Vector2<f32> {
+    let angle = rng.gen_range(0., 2. * ::std::f32::consts::PI);
+    vec2(angle.cos(), angle.sin())
+}
+
+fn rotate(vector: Vector2<f32>, angle: f32) -> Vector2<f32> {
+    let (sin, cos) = angle.sin_cos();
+    vec2(
+        vector.x * cos - vector.y * sin,
+        vector.x * sin + vector.y * cos,
+    )
+}
+
+pub struct GameState {
+    player_id: EntityId,
+    /// The entity drawing the player's thruster flame, while they're thrusting
+    thruster_flame_id: Option<EntityId>,
+    /// Counts down while the player is destroyed
+    player_respawn_seconds: Option<f32>,
+    sprites: Sprites,
+    entity_id_allocator: EntityIdAllocator,
+    rng: XorShiftRng,
+    collision_map: CollisionMap,
+    visibility_map: VisibilityMap,
+    physics: FnvHashMap<EntityId, Physics>,
+    previous_physics: FnvHashMap<EntityId, Physics>,
+    graphics: FnvHashMap<EntityId, Graphics>,
+    collision_response: FnvHashMap<EntityId, CollisionResponse>,
+    weapons: FnvHashMap<EntityId, Weapon>,
+    flight_models: FnvHashMap<EntityId, FlightModel>,
+    health: FnvHashMap<EntityId, Health>,
+    lights: FnvHashMap<EntityId, Light>,
+    flashes: FnvHashMap<EntityId, Flash>,
+    animated: FnvHashMap<EntityId, Animated>,
+    projectiles: FnvHashMap<EntityId, Projectile>,
+    asteroids: FnvHashMap<EntityId, Asteroid>,
+    events: Vec<GameEvent>,
+}
+
+/// Something which happened during the most recent tick, for the benefit of systems outside
+/// the simulation such as the camera.
+#[derive(Debug, Clone, Copy)]
+pub enum GameEvent {
+    AsteroidHit {
+        position: Vector2<f32>,
+        size: f32,
+    },
+    /// Two entities ran into each other, changing each one's momentum by `impulse`.
+    Collision {
+        position: Vector2<f32>,
+        impulse: f32,
+        involves_player: bool,
+    },
+    /// An entity's health ran out
+    Destroyed {
+        position: Vector2<f32>,
+        is_player: bool,
+    },
+}
+
+pub struct ToRender<'a> {
+    pub graphics: &'a Graphics,
+    pub physics: Physics,
+}
+
+pub struct ToLight<'a> {
+    pub light: &'a Light,
+    pub position: Vector2<f32>,
+}
+
+pub struct PlayerInfo {
+    pub physics: Physics,
+}
+
+impl GameState {
+    /// Panics if `sprite_atlas` is missing any of the sprites or animations the game uses.
+    pub fn new(map_image: &RgbaImage, sprite_atlas: &SpriteAtlas, seed: u64) -> Self {
+        let mut entity_id_allocator = EntityIdAllocator::default();
+        let player_id = entity_id_allocator.allocate();
+        let mut game_state = Self {
+            player_id,
+            thruster_flame_id: None,
+            player_respawn_seconds: None,
+            sprites: Sprites::from_atlas(sprite_atlas),
+            entity_id_allocator,
+            rng: XorShiftRng::seed_from_u64(seed),
+            collision_map: CollisionMap::from_image(map_image),
+            visibility_map: VisibilityMap::from_image(map_image),
+            physics: Default::default(),
+            previous_physics: Default::default(),
+            graphics: Default::default(),
+            collision_response: Default::default(),
+            weapons: Default::default(),
+            flight_models: Default::default(),
+            health: Default::default(),
+            lights: Default::default(),
+            flashes: Default::default(),
+            animated: Default::default(),
+            projectiles: Default::default(),
+            asteroids: Default::default(),
+            events: Vec::new(),
+        };
+        game_state.spawn_player();
+        game_state.populate_asteroids();
+        game_state
+    }
+    /// Gives the player entity everything it needs to fly, at the spawn position
+    fn spawn_player(&mut self) {
+        let player_id = self.player_id;
+        self.physics.insert(
+            player_id,
+            Physics {
+                centre_position: PLAYER_SPAWN_POSITION,
+                bounding_dimensions: vec2(32., 64.),
+                velocity: vec2(0., 0.),
+                facing: vec2(1., -1.).normalize(),
+                angular_velocity: 0.,
+                max_angular_speed: PLAYER_MAX_ANGULAR_SPEED,
+                angular_damping: PLAYER_ANGULAR_DAMPING,
+                mass: PLAYER_MASS,
+            },
+        );
+        // The player reappears rather than moving to the spawn position from where they
+        // were destroyed
+        self.previous_physics.remove(&player_id);
+        self.graphics.insert(
+            player_id,
+            Graphics {
+                sprite: self.sprites.player,
+                layer: RenderLayer::Overlay,
+                occludes: false,
+            },
+        );
+        self.collision_response
+            .insert(player_id, CollisionResponse::Slide);
+        let blaster = Weapon::blaster(self.sprites.projectile);
+        self.weapons.insert(player_id, blaster);
+        self.flight_models.insert(player_id, FlightModel::fighter());
+        self.health.insert(
+            player_id,
+            Health {
+                current: PLAYER_HEALTH,
+            },
+        );
+        self.lights.insert(player_id, PLAYER_LIGHT);
+    }
+    /// Reduces an entity's health by `amount`, destroying it if none is left. `direction` is
+    /// the direction the damage came from, pointing into the entity.
+    fn damage(&mut self, id: EntityId, amount: f32, direction: Vector2<f32>) {
+        let destroyed = match self.health.get_mut(&id) {
+            Some(health) => {
+                health.current -= amount;
+                health.current <= 0.
+            }
+            None => false,
+        };
+        if destroyed {
+            self.destroy(id, direction);
+        }
+    }
+    fn destroy(&mut self, id: EntityId, direction: Vector2<f32>) {
+        let position = self.physics[&id].centre_position;
+        let is_player = id == self.player_id;
+        self.events.push(GameEvent::Destroyed {
+            position,
+            is_player,
+        });
+        if is_player {
+            // The player entity has to stay where it was, without anything which would
+            // let it interact with the world, so there's somewhere to look while it's gone
+            let physics = Physics {
+                velocity: vec2(0., 0.),
+                angular_velocity: 0.,
+                ..self.physics[&id]
+            };
+            self.add_explosion(physics.oriented_box(), physics.velocity);
+            self.despawn(id);
+            self.player_id = self.entity_id_allocator.allocate();
+            self.physics.insert(self.player_id, physics);
+            // Projectiles still in flight mustn't be able to hit the player who fired them
+            for projectile in self.projectiles.values_mut() {
+                if projectile.owner == id {
+                    projectile.owner = self.player_id;
+                }
+            }
+            self.player_respawn_seconds = Some(PLAYER_RESPAWN_SECONDS);
+        } else if self.asteroids.contains_key(&id) {
+            self.split_asteroid(id, direction);
+        } else {
+            self.despawn(id);
+        }
+    }
+    fn populate_asteroids(&mut self) {
+        let player_position = self.physics[&self.player_id].centre_position;
+        let world_size = self.collision_map.size();
+        let mut num_asteroids = 0;
+        // Give up eventually in case the map has no room for asteroids
+        for _ in 0..NUM_ASTEROIDS * 100 {
+            if num_asteroids == NUM_ASTEROIDS {
+                break;
+            }
+            let centre_position = vec2(
+                self.rng.gen_range(0., world_size.x),
+                self.rng.gen_range(0., world_size.y),
+            );
+            if (centre_position - player_position).magnitude()
+                < MIN_ASTEROID_DISTANCE_FROM_PLAYER
+            {
+                continue;
+            }
+            let shape = OrientedBox {
+                centre: centre_position,
+                dimensions: vec2(ASTEROID_SIZE, ASTEROID_SIZE),
+                facing: random_unit_vector(&mut self.rng),
+            };
+            if self.collision_map.collides(&shape) {
+                continue;
+            }
+            let velocity =
+                random_unit_vector(&mut self.rng) * self.rng.gen_range(12., 60.);
+            self.add_asteroid(shape, velocity);
+            num_asteroids += 1;
+        }
+    }
+    fn add_asteroid(&mut self, shape: OrientedBox, velocity: Vector2<f32>) {
+        let id = self.entity_id_allocator.allocate();
+        self.physics.insert(
+            id,
+            Physics {
+                centre_position: shape.centre,
+                bounding_dimensions: shape.dimensions,
+                velocity,
+                facing: shape.facing,
+                angular_velocity: self.rng.gen_range(-2., 2.),
+                max_angular_speed: MAX_ASTEROID_ANGULAR_SPEED,
+                angular_damping: 0.,
+                mass: shape.dimensions.x * shape.dimensions.y * ASTEROID_DENSITY,
+            },
+        );
+        self.health.insert(
+            id,
+            Health {
+                current: shape.dimensions.x * ASTEROID_HEALTH_PER_PIXEL,
+            },
+        );
+        self.graphics.insert(
+            id,
+            Graphics {
+                sprite: self.sprites.asteroid,
+                layer: RenderLayer::World,
+                occludes: true,
+            },
+        );
+        self.collision_response
+            .insert(id, CollisionResponse::Bounce { restitution: 1. });
+        self.asteroids.insert(id, Asteroid);
+    }
+    /// Replaces an asteroid with two smaller asteroids flying apart perpendicular to the
+    /// direction of the impact which destroyed it. Asteroids which are already small are
+    /// destroyed outright.
+    fn split_asteroid(&mut self, id: EntityId, impact_direction: Vector2<f32>) {
+        let (shape, velocity) = {
+            let physics = &self.physics[&id];
+            (physics.oriented_box(), physics.velocity)
+        };
+        self.despawn(id);
+        self.events.push(GameEvent::AsteroidHit {
+            position: shape.centre,
+            size: shape.dimensions.x,
+        });
+        self.add_flash(
+            shape.centre,
+            Light {
+                colour: ASTEROID_HIT_FLASH_COLOUR,
+                radius: shape.dimensions.x * ASTEROID_HIT_FLASH_RADIUS_SCALE,
+                intensity: 1.,
+            },
+            ASTEROID_HIT_FLASH_SECONDS,
+        );
+        self.add_explosion(
+            OrientedBox {
+                dimensions: shape.dimensions * EXPLOSION_SCALE,
+                ..shape
+            },
+            velocity,
+        );
+        let size = shape.dimensions.x / 2.;
+        if size < MIN_ASTEROID_SIZE {
+            return;
+        }
+        // A projectile fired backwards while flying at about its own speed barely moves, so
+        // has no direction to split along
+        let direction = if impact_direction.magnitude2() > 0.001 {
+            impact_direction.normalize()
+        } else {
+            random_unit_vector(&mut self.rng)
+        };
+        let perpendicular = vec2(-direction.y, direction.x);
+        let push = velocity + direction * 30.;
+        for &side in [-1., 1.].iter() {
+            let offset = perpendicular * side;
+            let mut child_shape = OrientedBox {
+                centre: shape.centre + offset * size / 2.,
+                dimensions: vec2(size, size),
+                facing: random_unit_vector(&mut self.rng),
+            };
+            if self.collision_map.collides(&child_shape) {
+                // Fall back to a position entirely within the parent's footprint
+                child_shape.centre = shape.centre;
+                child_shape.facing = shape.facing;
+            }
+            let speed = self.rng.gen_range(30., 90.);
+            self.add_asteroid(child_shape, push + offset * speed);
+        }
+    }
+    fn add_flash(&mut self, position: Vector2<f32>, light: Light, duration: f32) {
+        let id = self.entity_id_allocator.allocate();
+        self.physics.insert(
+            id,
+            Physics {
+                centre_position: position,
+                bounding_dimensions: vec2(0., 0.),
+                velocity: vec2(0., 0.),
+                facing: vec2(1., 0.),
+                angular_velocity: 0.,
+                max_angular_speed: 0.,
+                angular_damping: 0.,
+                mass: 0.,
+            },
+        );
+        self.flashes.insert(
+            id,
+            Flash {
+                time_remaining: duration,
+                duration,
+                initial_intensity: light.intensity,
+            },
+        );
+        self.lights.insert(id, light);
+    }
+    fn add_animated(&mut self, physics: Physics, animation: Animation) -> EntityId {
+        let id = self.entity_id_allocator.allocate();
+        self.physics.insert(id, physics);
+        self.graphics.insert(
+            id,
+            Graphics {
+                sprite: animation.frames[0],
+                layer: RenderLayer::Overlay,
+                occludes: false,
+            },
+        );
+        self.animated.insert(
+            id,
+            Animated {
+                animation,
+                seconds: 0.,
+            },
+        );
+        id
+    }
+    fn add_explosion(&mut self, shape: OrientedBox, velocity: Vector2<f32>) {
+        let explosion = self.sprites.explosion.clone();
+        self.add_animated(
+            Physics {
+                centre_position: shape.centre,
+                bounding_dimensions: shape.dimensions,
+                velocity,
+                facing: shape.facing,
+                angular_velocity: 0.,
+                max_angular_speed: 0.,
+                angular_damping: 0.,
+                mass: 0.,
+            },
+            explosion,
+        );
+    }
+    fn interpolated_physics(&self, id: EntityId, alpha: f32) -> Option<Physics> {
+        self.physics.get(&id).map(|physics| {
+            self.previous_physics
+                .get(&id)
+                .map_or(*physics, |previous| physics.interpolate(previous, alpha))
+        })
+    }
+    pub fn to_render(&self, alpha: f32) -> impl Iterator<Item = ToRender<'_>> {
+        self.graphics.iter().filter_map(move |(&id, graphics)| {
+            self.interpolated_physics(id, alpha)
+                .map(|physics| ToRender { physics, graphics })
+        })
+    }
+    pub fn to_light(&self, alpha: f32) -> impl Iterator<Item = ToLight<'_>> {
+        self.lights.iter().filter_map(move |(&id, light)| {
+            self.interpolated_physics(id, alpha).map(|physics| ToLight {
+                light,
+                position: physics.centre_position,
+            })
+        })
+    }
+    pub fn update(&mut self, input_model: &InputModel) {
+        self.previous_physics.clone_from(&self.physics);
+        self.events.clear();
+
+        for (id, physics) in self.physics.iter_mut() {
+            if let Some(&response) = self.collision_response.get(id) {
+                let (centre_position, displacement) =
+                    self.collision_map.resolve_movement(
+                        &physics.oriented_box(),
+                        physics.velocity * TICK_SECONDS,
+                        response,
+                    );
+                physics.centre_position = centre_position;
+                physics.velocity = displacement / TICK_SECONDS;
+            } else {
+                physics.centre_position += physics.velocity * TICK_SECONDS;
+            }
+
+            physics.angular_velocity = (physics.angular_velocity
+                * (-physics.angular_damping * TICK_SECONDS).exp())
+            .max(-physics.max_angular_speed)
+            .min(physics.max_angular_speed);
+            if physics.angular_velocity != 0. {
+                let turned = OrientedBox {
+                    facing: rotate(
+                        physics.facing,
+                        physics.angular_velocity * TICK_SECONDS,
+                    ),
+                    ..physics.oriented_box()
+                };
+                // Entities which collide with the map can't turn into it
+                if !self.collision_response.contains_key(id)
+                    || !self.collision_map.collides(&turned)
+                {
+                    physics.facing = turned.facing;
+                }
+            }
+        }
+
+        self.resolve_entity_collisions();
+
+        let mut expired_projectiles = Vec::new();
+        let mut hits = Vec::new();
+        for (id, target_id) in self.projectile_hits() {
+            let projectile = self.projectiles.get_mut(&id).unwrap();
+            let physics = &self.physics[&id];
+            let hit_wall = self.collision_map.line_collides(
+                physics.centre_position - physics.velocity * TICK_SECONDS,
+                physics.centre_position,
+            );
+            if let Some(target_id) = target_id {
+                hits.push((target_id, projectile.damage, physics.velocity));
+                expired_projectiles.push(id);
+            } else if hit_wall || projectile.time_remaining <= 0. {
+                expired_projectiles.push(id);
+            } else {
+                projectile.time_remaining -= TICK_SECONDS;
+            }
+        }
+        for id in expired_projectiles {
+            self.despawn(id);
+        }
+        for (id, damage, projectile_velocity) in hits {
+            self.damage(id, damage, projectile_velocity);
+        }
+
+        let mut expired_flashes = Vec::new();
+        for (id, flash) in self.flashes.iter_mut() {
+            flash.time_remaining -= TICK_SECONDS;
+            if flash.time_remaining <= 0. {
+                expired_flashes.push(*id);
+            } else if let Some(light) = self.lights.get_mut(id) {
+                light.intensity =
+                    flash.initial_intensity * flash.time_remaining / flash.duration;
+            }
+        }
+        for id in expired_flashes {
+            self.despawn(id);
+        }
+
+        let mut finished_animations = Vec::new();
+        for (id, animated) in self.animated.iter_mut() {
+            animated.seconds += TICK_SECONDS;
+            match animated.animation.frame(animated.seconds) {
+                Some(sprite) => {
+                    if let Some(graphics) = self.graphics.get_mut(id) {
+                        graphics.sprite = sprite;
+                    }
+                }
+                None => finished_animations.push(*id),
+            }
+        }
+        for id in finished_animations {
+            self.despawn(id);
+        }
+
+        let world_size = self.collision_map.size();
+        let escaped_asteroids = self
+            .asteroids
+            .keys()
+            .filter(|id| {
+                let position = self.physics[id].centre_position;
+                position.x < 0.
+                    || position.y < 0.
+                    || position.x > world_size.x
+                    || position.y > world_size.y
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        for id in escaped_asteroids {
+            self.despawn(id);
+        }
+
+        if let Some(seconds) = self.player_respawn_seconds {
+            if seconds <= 0. {
+                self.player_respawn_seconds = None;
+                self.spawn_player();
+            } else {
+                self.player_respawn_seconds = Some(seconds - TICK_SECONDS);
+            }
+        }
+
+        let player_is_alive = self.player_respawn_seconds.is_none();
+        if let Some(physics) = self
+            .physics
+            .get_mut(&self.player_id)
+            .filter(|_| player_is_alive)
+        {
+            if let Some(aim_vector) = input_model.aim_vector() {
+                // Turn towards the aim, slowing down on the approach so as not to overshoot
+                let angle_to_aim = (physics.facing.x * aim_vector.y
+                    - physics.facing.y * aim_vector.x)
+                    .atan2(physics.facing.dot(aim_vector));
+                let target_angular_velocity = angle_to_aim * PLAYER_TURN_RESPONSIVENESS;
+                let max_change = PLAYER_ANGULAR_ACCELERATION * TICK_SECONDS;
+                physics.angular_velocity += (target_angular_velocity
+                    - physics.angular_velocity)
+                    .max(-max_change)
+                    .min(max_change);
+            }
+        }
+
+        let main_engine_throttle = self.update_player_flight(input_model);
+        self.update_player_weapon(input_model);
+        self.update_thruster_flame(main_engine_throttle > 0.);
+    }
+    /// Fires the player's thrusters as the controls and their ship's flight model dictate,
+    /// then applies drag and the speed limit. Returns how hard the main engine is firing,
+    /// from 0 to 1, or a negative value if the reverse thrusters are firing instead.
+    fn update_player_flight(&mut self, input_model: &InputModel) -> f32 {
+        let flight_model = match self.flight_models.get(&self.player_id) {
+            Some(&flight_model) => flight_model,
+            None => return 0.,
+        };
+        let physics = match self.physics.get_mut(&self.player_id) {
+            Some(physics) => physics,
+            None => return 0.,
+        };
+        let right = vec2(-physics.facing.y, physics.facing.x);
+        let mut thrust = input_model.thrust();
+        let mut strafe = input_model.strafe();
+        if flight_model.has_auto_brake && input_model.auto_brake() {
+            // Along each axis the pilot isn't thrusting, fire whichever thruster opposes the
+            // ship's movement, no harder than needed to stop it this tick
+            let throttle_to_stop = |speed: f32, acceleration: f32| {
+                if acceleration > 0. {
+                    (-speed / (acceleration * TICK_SECONDS)).clamp(-1., 1.)
+                } else {
+                    0.
+                }
+            };
+            if thrust == 0. {
+                let forward_speed = physics.velocity.dot(physics.facing);
+                thrust = if forward_speed > 0. {
+                    throttle_to_stop(forward_speed, flight_model.reverse_acceleration)
+                } else {
+                    throttle_to_stop(forward_speed, flight_model.thrust_acceleration)
+                };
+            }
+            if strafe == 0. {
+                strafe = throttle_to_stop(
+                    physics.velocity.dot(right),
+                    flight_model.strafe_acceleration,
+                );
+            }
+        }
+        let forward_acceleration = if thrust > 0. {
+            thrust * flight_model.thrust_acceleration
+        } else {
+            thrust * flight_model.reverse_acceleration
+        };
+        let acceleration = physics.facing * forward_acceleration
+            + right * strafe * flight_model.strafe_acceleration;
+        physics.velocity = (physics.velocity + acceleration * TICK_SECONDS)
+            * (-flight_model.linear_drag * TICK_SECONDS).exp();
+        let speed = physics.velocity.magnitude();
+        if speed > flight_model.max_speed {
+            physics.velocity *= flight_model.max_speed / speed;
+        }
+        thrust
+    }
+
+    /// Returns every projectile in order of ID, along with the nearest entity it hit on the
+    /// way from its previous position, if any. Projectiles which hit the map first also have
+    /// no target, and the whole path is checked so fast ones can't pass through small targets.
+    fn projectile_hits(&self) -> Vec<(EntityId, Option<EntityId>)> {
+        let mut target_ids = self.health.keys().cloned().collect::<Vec<_>>();
+        target_ids.sort();
+        let mut projectile_ids = self.projectiles.keys().cloned().collect::<Vec<_>>();
+        projectile_ids.sort();
+        let paths = projectile_ids
+            .iter()
+            .map(|id| {
+                let physics = &self.physics[id];
+                let start = physics.centre_position - physics.velocity * TICK_SECONDS;
+                (start, physics.centre_position)
+            })
+            .collect::<Vec<_>>();
+        // Targets come before paths, so each pair of a target and a path has the target first
+        let shapes = target_ids
+            .iter()
+            .map(|id| self.physics[id].oriented_box())
+            .chain(
+                paths
+                    .iter()
+                    .map(|&(start, end)| OrientedBox::around_segment(start, end)),
+            )
+            .collect::<Vec<_>>();
+        let mut nearest_hits: Vec<Option<(f32, EntityId)>> = vec![None; paths.len()];
+        for (a, b) in collision::overlapping_pairs(&shapes) {
+            if a >= target_ids.len() || b < target_ids.len() {
+                continue;
+            }
+            let (target_id, index) = (target_ids[a], b - target_ids.len());
+            if target_id == self.projectiles[&projectile_ids[index]].owner {
+                continue;
+            }
+            let (start, end) = paths[index];
+            if let Some(fraction) = shapes[a].segment_entry(start, end) {
+                if nearest_hits[index].is_none_or(|(nearest, _)| fraction < nearest) {
+                    nearest_hits[index] = Some((fraction, target_id));
+                }
+            }
+        }
+        projectile_ids
+            .into_iter()
+            .zip(paths)
+            .zip(nearest_hits)
+            .map(|((id, (start, end)), nearest_hit)| {
+                let hit = nearest_hit.and_then(|(fraction, target_id)| {
+                    let hit_position = start + (end - start) * fraction;
+                    if self.collision_map.line_collides(start, hit_position) {
+                        None
+                    } else {
+                        Some(target_id)
+                    }
+                });
+                (id, hit)
+            })
+            .collect()
+    }
+
+    /// Pushes apart entities which have moved into each other, and exchanges momentum
+    /// between them if they're moving towards each other. Entities aren't pushed into the
+    /// map, so one wedged between another entity and a wall may stay overlapping it.
+    fn resolve_entity_collisions(&mut self) {
+        let mut ids = self
+            .collision_response
+            .keys()
+            .filter(|id| self.physics.contains_key(id))
+            .cloned()
+            .collect::<Vec<_>>();
+        ids.sort();
+        let mut damaged = Vec::new();
+        let shapes = ids
+            .iter()
+            .map(|id| self.physics[id].oriented_box())
+            .collect::<Vec<_>>();
+        for (a, b) in collision::overlapping_pairs(&shapes) {
+            let (id_a, id_b) = (ids[a], ids[b]);
+            let (mut physics_a, mut physics_b) =
+                (self.physics[&id_a], self.physics[&id_b]);
+            // Earlier collisions this tick may have moved either entity
+            let contact =
+                match physics_a.oriented_box().overlap(&physics_b.oriented_box()) {
+                    Some(contact) => contact,
+                    None => continue,
+                };
+            let (inverse_mass_a, inverse_mass_b) =
+                (1. / physics_a.mass, 1. / physics_b.mass);
+            let inverse_mass_sum = inverse_mass_a + inverse_mass_b;
+
+            let separation = contact.normal * contact.depth / inverse_mass_sum;
+            let separated_a = physics_a.centre_position - separation * inverse_mass_a;
+            let separated_b = physics_b.centre_position + separation * inverse_mass_b;
+            let collides_with_map = |physics: &Physics, centre_position| {
+                self.collision_map.collides(&OrientedBox {
+                    centre: centre_position,
+                    ..physics.oriented_box()
+                })
+            };
+            if !collides_with_map(&physics_a, separated_a) {
+                physics_a.centre_position = separated_a;
+            }
+            if !collides_with_map(&physics_b, separated_b) {
+                physics_b.centre_position = separated_b;
+            }
+
+            let closing_speed =
+                (physics_a.velocity - physics_b.velocity).dot(contact.normal);
+            if closing_speed > 0. {
+                let restitution = self.collision_response[&id_a]
+                    .restitution()
+                    .min(self.collision_response[&id_b].restitution());
+                let impulse = (1. + restitution) * closing_speed / inverse_mass_sum;
+                physics_a.velocity -= contact.normal * impulse * inverse_mass_a;
+                physics_b.velocity += contact.normal * impulse * inverse_mass_b;
+                self.events.push(GameEvent::Collision {
+                    position: (physics_a.centre_position + physics_b.centre_position)
+                        / 2.,
+                    impulse,
+                    involves_player: id_a == self.player_id || id_b == self.player_id,
+                });
+                let collision_damage = |speed_change: f32| {
+                    (speed_change - MIN_DAMAGING_SPEED_CHANGE).max(0.)
+                        * COLLISION_DAMAGE_PER_SPEED_CHANGE
+                };
+                damaged.push((
+                    id_a,
+                    collision_damage(impulse * inverse_mass_a),
+                    -contact.normal,
+                ));
+                damaged.push((
+                    id_b,
+                    collision_damage(impulse * inverse_mass_b),
+                    contact.normal,
+                ));
+            }
+            self.physics.insert(id_a, physics_a);
+            self.physics.insert(id_b, physics_b);
+        }
+        for (id, damage, direction) in damaged {
+            // An earlier collision this tick may already have destroyed the entity
+            if damage > 0. && self.entity_id_allocator.is_live(id) {
+                self.damage(id, damage, direction);
+            }
+        }
+    }
+    /// Shows the thruster flame while the main engine is firing, keeping it behind the player.
+    fn update_thruster_flame(&mut self, thrusting: bool) {
+        let player_physics = match self.physics.get(&self.player_id) {
+            Some(&physics) => physics,
+            None => return,
+        };
+        match self.thruster_flame_id {
+            Some(id) if !thrusting => {
+                self.despawn(id);
+                self.thruster_flame_id = None;
+            }
+            None if thrusting => {
+                let thruster_flame = self.sprites.thruster_flame.clone();
+                let id = self.add_animated(player_physics, thruster_flame);
+                self.thruster_flame_id = Some(id);
+            }
+            _ => (),
+        }
+        if let Some(id) = self.thruster_flame_id {
+            let distance_behind =
+                (player_physics.bounding_dimensions.y + THRUSTER_FLAME_DIMENSIONS.y) / 2.;
+            if let Some(physics) = self.physics.get_mut(&id) {
+                *physics = Physics {
+                    centre_position: player_physics.centre_position
+                        - player_physics.facing * distance_behind,
+                    bounding_dimensions: THRUSTER_FLAME_DIMENSIONS,
+                    mass: 0.,
+                    ..player_physics
+                };
+            }
+        }
+    }
+    fn update_player_weapon(&mut self, input_model: &InputModel) {
+        let (
+            projectile_physics,
+            projectile_graphics,
+            projectile_light,
+            projectile_lifetime,
+            projectile_damage,
+        ) = {
+            let weapon = match self.weapons.get_mut(&self.player_id) {
+                Some(weapon) => weapon,
+                None => return,
+            };
+            weapon.time_until_reloaded -= TICK_SECONDS;
+            if !input_model.is_shooting() || weapon.time_until_reloaded > 0. {
+                return;
+            }
+            weapon.time_until_reloaded = weapon.reload_time;
+            let shooter = &self.physics[&self.player_id];
+            // Spawn the projectile just in front of the shooter's nose
+            let nose_distance =
+                (shooter.bounding_dimensions.y + weapon.projectile_dimensions.y) / 2.;
+            let projectile_physics = Physics {
+                centre_position: shooter.centre_position + shooter.facing * nose_distance,
+                bounding_dimensions: weapon.projectile_dimensions,
+                velocity: shooter.velocity + shooter.facing * weapon.projectile_speed,
+                facing: shooter.facing,
+                angular_velocity: 0.,
+                max_angular_speed: 0.,
+                angular_damping: 0.,
+                mass: 0.,
+            };
+            (
+                projectile_physics,
+                weapon.projectile_graphics.clone(),
+                weapon.projectile_light,
+                weapon.projectile_lifetime,
+                weapon.projectile_damage,
+            )
+        };
+        let id = self.entity_id_allocator.allocate();
+        self.physics.insert(id, projectile_physics);
+        self.graphics.insert(id, projectile_graphics);
+        self.lights.insert(id, projectile_light);
+        self.projectiles.insert(
+            id,
+            Projectile {
+                time_remaining: projectile_lifetime,
+                damage: projectile_damage,
+                owner: self.player_id,
+            },
+        );
+    }
+    /// Removes an entity from every component map and frees its ID. Does nothing if `id`
+    /// is stale, because the entity has already been despawned.
+    fn despawn(&mut self, id: EntityId) {
+        if !self.entity_id_allocator.free(id) {
+            return;
+        }
+        self.physics.remove(&id);
+        self.previous_physics.remove(&id);
+        self.graphics.remove(&id);
+        self.collision_response.remove(&id);
+        self.weapons.remove(&id);
+        self.flight_models.remove(&id);
+        self.health.remove(&id);
+        self.lights.remove(&id);
+        self.flashes.remove(&id);
+        self.animated.remove(&id);
+        self.projectiles.remove(&id);
+        self.asteroids.remove(&id);
+    }
+    pub fn events(&self) -> &[GameEvent] {
+        &self.events
+    }
+    pub fn world_size(&self) -> Vector2<f32> {
+        self.collision_map.size()
+    }
+    /// Hashes the exact bit patterns of every entity's physics state, in order of entity
+    /// id. Two runs which agree on this after every tick have behaved identically as far
+    /// as anything visible is concerned.
+    pub fn physics_checksum(&self) -> u64 {
+        let mut ids = self.physics.keys().cloned().collect::<Vec<_>>();
+        ids.sort();
+        let mut hasher = FnvHasher::default();
+        for id in ids {
+            let physics = &self.physics[&id];
+            hasher.write(&id.index.to_le_bytes());
+            hasher.write(&id.generation.to_le_bytes());
+            for value in &[
+                physics.centre_position.x,
+                physics.centre_position.y,
+                physics.bounding_dimensions.x,
+                physics.bounding_dimensions.y,
+                physics.velocity.x,
+                physics.velocity.y,
+                physics.facing.x,
+                physics.facing.y,
+                physics.angular_velocity,
+                physics.max_angular_speed,
+                physics.angular_damping,
+                physics.mass,
+            ] {
+                hasher.write(&value.to_bits().to_le_bytes());
+            }
+        }
+        hasher.finish()
+    }
+    pub fn num_entities(&self) -> usize {
+        self.physics.len()
+    }
+    /// Replaces the map, keeping all entities where they are. Entities left overlapping
+    /// the new map can move freely until they're clear of it.
+    pub fn set_map(&mut self, map_image: &RgbaImage) {
+        self.collision_map = CollisionMap::from_image(map_image);
+        self.visibility_map = VisibilityMap::from_image(map_image);
+    }
+    /// Returns true if the player has an unobstructed line of sight to `position`, by the
+    /// same test the lighting shader uses to decide what to draw. Only the map is tested
+    /// against, so occluding sprites don't block the view as they do in the shader.
+    pub fn is_visible_from_player(&self, position: Vector2<f32>) -> bool {
+        let eye = self.physics[&self.player_id].centre_position;
+        self.visibility_map.is_visible(position, eye)
+    }
+    pub fn player_info(&self, alpha: f32) -> PlayerInfo {
+        PlayerInfo {
+            physics: self
+                .interpolated_physics(self.player_id, alpha)
+                .expect("no player physics"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use map;
+
+    #[test]
+    fn freed_indices_are_reused() {
+        let mut allocator = EntityIdAllocator::default();
+        let first = allocator.allocate();
+        let second = allocator.allocate();
+        assert_ne!(first.index, second.index);
+        assert!(allocator.free(first));
+        assert_eq!(allocator.allocate().index, first.index);
+        assert_eq!(allocator.allocate().index, 2);
+    }
+
+    #[test]
+    fn reused_indices_get_a_new_generation() {
+        let mut allocator = EntityIdAllocator::default();
+        let old_id = allocator.allocate();
+        allocator.free(old_id);
+        let new_id = allocator.allocate();
+        assert_eq!(new_id.index, old_id.index);
+        assert_ne!(new_id.generation, old_id.generation);
+        assert!(!allocator.is_live(old_id));
+        assert!(allocator.is_live(new_id));
+    }
+
+    #[test]
+    fn freeing_a_stale_id_does_nothing() {
+        let mut allocator = EntityIdAllocator::default();
+        let old_id = allocator.allocate();
+        allocator.free(old_id);
+        let new_id = allocator.allocate();
+        assert!(!allocator.free(old_id));
+        assert!(allocator.is_live(new_id));
+        // The index mustn't have been freed a second time and handed out twice
+        assert_ne!(allocator.allocate().index, new_id.index);
+    }
+
+    fn new_game_state() -> GameState {
+        let map_image = map::load_builtin().unwrap();
+        let sprite_atlas = SpriteAtlas::builtin().unwrap();
+        GameState::new(&map_image, &sprite_atlas, 0)
+    }
+
+    #[test]
+    fn despawning_a_stale_id_leaves_the_new_occupant_intact() {
+        let mut game_state = new_game_state();
+        let physics = game_state.player_info(0.).physics;
+        let explosion = game_state.sprites.explosion.clone();
+
+        let old_id = game_state.add_animated(physics, explosion.clone());
+        game_state.despawn(old_id);
+        let num_entities = game_state.num_entities();
+        let new_id = game_state.add_animated(physics, explosion);
+        assert_eq!(new_id.index, old_id.index);
+
+        game_state.despawn(old_id);
+        assert_eq!(game_state.num_entities(), num_entities + 1);
+        assert!(game_state.physics.contains_key(&new_id));
+        assert!(game_state.graphics.contains_key(&new_id));
+        assert!(game_state.animated.contains_key(&new_id));
+    }
+
+    #[test]
+    fn projectiles_fired_before_a_respawn_miss_the_respawned_player() {
+        let mut game_state = new_game_state();
+        let old_player_id = game_state.player_id;
+        let projectile_id = game_state.entity_id_allocator.allocate();
+        game_state.projectiles.insert(
+            projectile_id,
+            Projectile {
+                time_remaining: 1.,
+                damage: 1.,
+                owner: old_player_id,
+            },
+        );
+
+        game_state.destroy(old_player_id, vec2(0., 0.));
+        game_state.spawn_player();
+        assert_ne!(game_state.player_id, old_player_id);
+
+        // Put the projectile right on the respawned player
+        let player_physics = game_state.physics[&game_state.player_id];
+        game_state.physics.insert(
+            projectile_id,
+            Physics {
+                bounding_dimensions: vec2(2., 2.),
+                velocity: vec2(1., 0.),
+                mass: 0.,
+                ..player_physics
+            },
+        );
+        assert!(game_state
+            .projectile_hits()
+            .iter()
+            .all(|&(id, target_id)| id != projectile_id
+                || target_id != Some(game_state.player_id)));
+    }
+
+    #[test]
+    fn asteroids_split_without_an_impact_direction() {
+        let mut game_state = new_game_state();
+        let id = *game_state.asteroids.keys().min().unwrap();
+        let num_asteroids = game_state.asteroids.len();
+        game_state.split_asteroid(id, vec2(0., 0.));
+        assert_eq!(game_state.asteroids.len(), num_asteroids + 1);
+        for id in game_state.asteroids.keys() {
+            let physics = &game_state.physics[id];
+            assert!(physics.centre_position.x.is_finite());
+            assert!(physics.centre_position.y.is_finite());
+            assert!(physics.velocity.x.is_finite());
+            assert!(physics.velocity.y.is_finite());
+        }
+    }
+}
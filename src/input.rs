@@ -0,0 +1,296 @@
+use cgmath::{InnerSpace, Vector2, vec2};
+use std::fmt;
+
+#[derive(Debug, Default)]
+struct ButtonState {
+    current: bool,
+    previous: bool,
+}
+
+impl ButtonState {
+    fn progress(&mut self) {
+        self.previous = self.current;
+    }
+    fn press(&mut self) {
+        self.current = true;
+    }
+    fn release(&mut self) {
+        self.current = false;
+    }
+    fn is_down(&self) -> bool {
+        self.current
+    }
+}
+
+#[derive(Debug)]
+pub struct InputModel {
+    aim_vec: Vector2<f32>,
+    shoot: ButtonState,
+    thrust: f32,
+    strafe: f32,
+    auto_brake_button: ButtonState,
+    auto_brake: bool,
+    zoom: f32,
+}
+
+impl Default for InputModel {
+    fn default() -> Self {
+        Self {
+            aim_vec: vec2(0., 0.),
+            shoot: ButtonState::default(),
+            thrust: 0.,
+            strafe: 0.,
+            auto_brake_button: ButtonState::default(),
+            auto_brake: false,
+            zoom: 0.,
+        }
+    }
+}
+
+/// Everything an `InputModel` passes on to the simulation at one instant, as stored in a
+/// replay.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InputSnapshot {
+    pub aim: Vector2<f32>,
+    pub shoot: bool,
+    pub thrust: f32,
+    pub strafe: f32,
+    pub auto_brake: bool,
+    pub zoom: f32,
+}
+
+const ANALOG_THRESHOLD: f32 = 0.1;
+
+fn analog_threshold_value(v: f32) -> f32 {
+    if v.abs() > ANALOG_THRESHOLD {
+        v
+    } else {
+        0.
+    }
+}
+
+impl InputModel {
+    pub fn progress_buttons(&mut self) {
+        self.shoot.progress();
+        self.auto_brake_button.progress();
+    }
+    pub fn press_shoot(&mut self) {
+        self.shoot.press();
+    }
+    pub fn release_shoot(&mut self) {
+        self.shoot.release();
+    }
+    pub fn set_aim_x(&mut self, value: f32) {
+        self.aim_vec.x = analog_threshold_value(value);
+    }
+    pub fn set_aim_y(&mut self, value: f32) {
+        self.aim_vec.y = analog_threshold_value(value);
+    }
+    /// Positive values thrust forwards and negative values backwards
+    pub fn set_thrust(&mut self, value: f32) {
+        self.thrust = analog_threshold_value(value).clamp(-1., 1.);
+    }
+    /// Positive values thrust to the right of the way the ship is facing, and negative
+    /// values to the left
+    pub fn set_strafe(&mut self, value: f32) {
+        self.strafe = analog_threshold_value(value).clamp(-1., 1.);
+    }
+    /// Turns auto-brake on if it's off and off if it's on. Holding the button down doesn't
+    /// toggle it again.
+    pub fn press_auto_brake(&mut self) {
+        if !self.auto_brake_button.is_down() {
+            self.auto_brake = !self.auto_brake;
+        }
+        self.auto_brake_button.press();
+    }
+    pub fn release_auto_brake(&mut self) {
+        self.auto_brake_button.release();
+    }
+    pub fn set_auto_brake(&mut self, auto_brake: bool) {
+        self.auto_brake = auto_brake;
+    }
+    /// Positive values zoom in and negative values zoom out
+    pub fn set_zoom(&mut self, value: f32) {
+        self.zoom = analog_threshold_value(value).clamp(-1., 1.);
+    }
+    pub fn zoom(&self) -> f32 {
+        self.zoom
+    }
+    pub fn thrust(&self) -> f32 {
+        self.thrust
+    }
+    pub fn strafe(&self) -> f32 {
+        self.strafe
+    }
+    /// True if the ship should fire its thrusters to cancel any movement the player isn't
+    /// asking for
+    pub fn auto_brake(&self) -> bool {
+        self.auto_brake
+    }
+    pub fn is_shooting(&self) -> bool {
+        self.shoot.is_down()
+    }
+    pub fn snapshot(&self) -> InputSnapshot {
+        InputSnapshot {
+            aim: self.aim_vec,
+            shoot: self.shoot.is_down(),
+            thrust: self.thrust,
+            strafe: self.strafe,
+            auto_brake: self.auto_brake,
+            zoom: self.zoom,
+        }
+    }
+    /// Puts the model into the state captured by `snapshot`, as though the controls had
+    /// been moved there since the last call to `progress_buttons`. Values are restored
+    /// exactly, without applying thresholds a second time.
+    pub fn restore(&mut self, snapshot: &InputSnapshot) {
+        self.aim_vec = snapshot.aim;
+        if snapshot.shoot {
+            self.shoot.press();
+        } else {
+            self.shoot.release();
+        }
+        self.thrust = snapshot.thrust;
+        self.strafe = snapshot.strafe;
+        self.auto_brake = snapshot.auto_brake;
+        self.zoom = snapshot.zoom;
+    }
+    pub fn aim_vector(&self) -> Option<Vector2<f32>> {
+        let magnitude2 = self.aim_vec.magnitude2();
+        if magnitude2 >= 1. {
+            Some(self.aim_vec.normalize())
+        } else {
+            const AIM_THRESHOLD2: f32 = 0.2;
+            if magnitude2 > AIM_THRESHOLD2 {
+                Some(self.aim_vec.normalize())
+            } else {
+                None
+            }
+        }
+    }
+}
+
+enum ScriptCommand {
+    Aim(f32, f32),
+    Thrust(f32),
+    Strafe(f32),
+    AutoBrake(bool),
+    Shoot(bool),
+    Zoom(f32),
+}
+
+#[derive(Debug)]
+pub struct ScriptError {
+    line_number: usize,
+    message: String,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line_number, self.message)
+    }
+}
+
+/// Drives an `InputModel` from a list of timestamped commands instead of a keyboard or
+/// gamepad. Each non-empty line not starting with '#' is of the form "<tick> <command>",
+/// where command is one of:
+///  - aim <x> <y>
+///  - thrust <amount>
+///  - strafe <amount>
+///  - auto-brake on|off
+///  - shoot press|release
+///  - zoom <amount>
+///
+/// Commands persist until overridden by a later command, as if a button was held.
+pub struct ScriptedInput {
+    commands: Vec<(u64, ScriptCommand)>,
+    next_index: usize,
+}
+
+fn parse_script_line(line: &str) -> Result<(u64, ScriptCommand), String> {
+    let mut words = line.split_whitespace();
+    let mut next_word =
+        |expected: &str| words.next().ok_or_else(|| format!("expected {}", expected));
+    let parse_f32 = |word: &str| {
+        word.parse::<f32>()
+            .map_err(|_| format!("invalid number \"{}\"", word))
+    };
+    let tick_word = next_word("tick")?;
+    let tick = tick_word
+        .parse::<u64>()
+        .map_err(|_| format!("invalid tick \"{}\"", tick_word))?;
+    let command = match next_word("command")? {
+        "aim" => {
+            let x = parse_f32(next_word("x")?)?;
+            let y = parse_f32(next_word("y")?)?;
+            ScriptCommand::Aim(x, y)
+        }
+        "thrust" => ScriptCommand::Thrust(parse_f32(next_word("amount")?)?),
+        "strafe" => ScriptCommand::Strafe(parse_f32(next_word("amount")?)?),
+        "auto-brake" => match next_word("on or off")? {
+            "on" => ScriptCommand::AutoBrake(true),
+            "off" => ScriptCommand::AutoBrake(false),
+            other => return Err(format!("expected on or off, got \"{}\"", other)),
+        },
+        "zoom" => ScriptCommand::Zoom(parse_f32(next_word("amount")?)?),
+        "shoot" => match next_word("press or release")? {
+            "press" => ScriptCommand::Shoot(true),
+            "release" => ScriptCommand::Shoot(false),
+            other => return Err(format!("expected press or release, got \"{}\"", other)),
+        },
+        other => return Err(format!("unknown command \"{}\"", other)),
+    };
+    if let Some(extra) = words.next() {
+        return Err(format!("unexpected \"{}\"", extra));
+    }
+    Ok((tick, command))
+}
+
+impl ScriptedInput {
+    pub fn parse(script: &str) -> Result<Self, ScriptError> {
+        let mut commands = Vec::new();
+        for (index, line) in script.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let command = parse_script_line(line).map_err(|message| ScriptError {
+                line_number: index + 1,
+                message,
+            })?;
+            commands.push(command);
+        }
+        // A stable sort keeps commands for the same tick in the order they were written
+        commands.sort_by_key(|&(tick, _)| tick);
+        Ok(Self {
+            commands,
+            next_index: 0,
+        })
+    }
+
+    /// Applies all commands scheduled for `tick` or earlier which haven't been applied yet.
+    pub fn apply(&mut self, tick: u64, input_model: &mut InputModel) {
+        input_model.progress_buttons();
+        while let Some(&(command_tick, ref command)) = self.commands.get(self.next_index)
+        {
+            if command_tick > tick {
+                break;
+            }
+            match *command {
+                ScriptCommand::Aim(x, y) => {
+                    input_model.set_aim_x(x);
+                    input_model.set_aim_y(y);
+                }
+                ScriptCommand::Thrust(amount) => input_model.set_thrust(amount),
+                ScriptCommand::Strafe(amount) => input_model.set_strafe(amount),
+                ScriptCommand::AutoBrake(auto_brake) => {
+                    input_model.set_auto_brake(auto_brake)
+                }
+                ScriptCommand::Shoot(true) => input_model.press_shoot(),
+                ScriptCommand::Shoot(false) => input_model.release_shoot(),
+                ScriptCommand::Zoom(amount) => input_model.set_zoom(amount),
+            }
+            self.next_index += 1;
+        }
+    }
+}
@@ -0,0 +1,18 @@
+extern crate cgmath;
+extern crate fnv;
+extern crate image;
+extern crate rand;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate toml;
+
+pub mod camera;
+pub mod collision;
+pub mod exploration;
+pub mod game_state;
+pub mod input;
+pub mod map;
+pub mod replay;
+pub mod sprite_atlas;
+pub mod visibility;
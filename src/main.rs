@@ -1,5 +1,5 @@
+extern crate belt;
 extern crate cgmath;
-extern crate fnv;
 #[macro_use]
 extern crate gfx;
 extern crate gfx_device_gl;
@@ -7,620 +7,233 @@ extern crate gfx_window_glutin;
 extern crate gilrs;
 extern crate glutin;
 extern crate image;
+extern crate rand;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate toml;
+
+mod bindings;
+mod renderer;
 
+use cgmath::vec2;
 use gfx::Device;
-use gfx::Factory;
 use glutin::GlContext;
 
-use cgmath::{InnerSpace, Vector2, vec2};
-use fnv::FnvHashMap;
-use image::GenericImage;
+use std::env;
+use std::io;
+use std::process;
+use std::time::Instant;
 
-type ColourFormat = gfx::format::Srgba8;
-type DepthFormat = gfx::format::DepthStencil;
-type Resources = gfx_device_gl::Resources;
+use belt::camera::Camera;
+use belt::exploration::{ExploredMap, ExploredMapError};
+use belt::game_state::{GameEvent, GameState, TICK_SECONDS};
+use belt::input::InputModel;
+use belt::map::{self, MapFile};
+use belt::replay::{self, Replay, ReplayRecorder};
+use belt::sprite_atlas::SpriteAtlas;
+use belt::visibility::VisionCone;
 
-type Format = (gfx::format::R8_G8_B8_A8, gfx::format::Srgb);
-type Surface = <Format as gfx::format::Formatted>::Surface;
-type View = <Format as gfx::format::Formatted>::View;
-
-const QUAD_INDICES: [u16; 6] = [0, 1, 2, 2, 3, 0];
-const QUAD_COORDS: [[f32; 2]; 4] = [[0.0, 0.0], [0.0, 1.0], [1.0, 1.0], [1.0, 0.0]];
-
-const MAX_NUM_QUADS: usize = 1024;
-
-gfx_constant_struct!(OutputProperties {
-    player_position_in_pixels: [f32; 2] = "u_PlayerPositionInPixels",
-    zoom: f32 = "u_Zoom",
-});
-
-gfx_constant_struct!(OutputPropertiesStatic {
-    window_size_in_pixels: [f32; 2] = "u_WindowSizeInPixels",
-    input_size_in_pixels: [f32; 2] = "u_InputSizeInPixels",
-});
-
-gfx_pipeline!(output_pipe {
-    quad_corners: gfx::VertexBuffer<QuadCorners> = (),
-    properties: gfx::ConstantBuffer<OutputProperties> = "Properties",
-    properties_static: gfx::ConstantBuffer<OutputPropertiesStatic> = "PropertiesStatic",
-    in_colour: gfx::TextureSampler<View> = "t_Colour",
-    out_colour: gfx::BlendTarget<ColourFormat> =
-        ("Target0", gfx::state::ColorMask::all(), gfx::preset::blend::ALPHA),
-});
-struct OutputRenderer<R: gfx::Resources> {
-    bundle: gfx::Bundle<R, output_pipe::Data<R>>,
-}
+use bindings::{Bindings, REBIND_KEY, Rebinding};
+use renderer::{ColourFormat, LightingSettings, Renderer};
 
-impl<R: gfx::Resources> OutputRenderer<R> {
-    pub fn new<F, C>(
-        colour_srv: gfx::handle::ShaderResourceView<R, View>,
-        srv_size: Vector2<f32>,
-        rtv: gfx::handle::RenderTargetView<R, ColourFormat>,
-        factory: &mut F,
-        encoder: &mut gfx::Encoder<R, C>,
-    ) -> Self
-    where
-        F: gfx::Factory<R> + gfx::traits::FactoryExt<R>,
-        C: gfx::CommandBuffer<R>,
-    {
-        let sampler = factory.create_sampler(gfx::texture::SamplerInfo::new(
-            gfx::texture::FilterMethod::Scale,
-            gfx::texture::WrapMode::Border,
-        ));
-
-        let pso = factory
-            .create_pipeline_simple(
-                include_bytes!("shaders/output/shader.150.vert"),
-                include_bytes!("shaders/output/shader.150.frag"),
-                output_pipe::new(),
-            )
-            .expect("Failed to create pipeline");
-
-        let quad_corners_data = QUAD_COORDS
-            .iter()
-            .map(|v| QuadCorners {
-                corner_zero_to_one: *v,
-            })
-            .collect::<Vec<_>>();
-
-        let (quad_corners_buf, slice) = factory
-            .create_vertex_buffer_with_slice(&quad_corners_data, &QUAD_INDICES[..]);
-
-        let data = output_pipe::Data {
-            quad_corners: quad_corners_buf,
-            properties: factory.create_constant_buffer(1),
-            properties_static: factory.create_constant_buffer(1),
-            in_colour: (colour_srv, sampler.clone()),
-            out_colour: rtv,
-        };
-        let bundle = gfx::pso::bundle::Bundle::new(slice, pso, data);
-        let (window_width, window_height, _, _) = bundle.data.out_colour.get_dimensions();
-        let properties_static = OutputPropertiesStatic {
-            window_size_in_pixels: [window_width as f32, window_height as f32],
-            input_size_in_pixels: srv_size.into(),
-        };
-        encoder
-            .update_constant_buffer(&bundle.data.properties_static, &properties_static);
-        Self { bundle }
-    }
-
-    fn update<C>(
-        &self,
-        player_info: PlayerInfo,
-        zoom: f32,
-        encoder: &mut gfx::Encoder<R, C>,
-    ) where
-        C: gfx::CommandBuffer<R>,
-    {
-        let properties = OutputProperties {
-            player_position_in_pixels: player_info.physics.centre_position.into(),
-            zoom,
-        };
-        encoder.update_constant_buffer(&self.bundle.data.properties, &properties);
-    }
-    fn encode<C>(&self, encoder: &mut gfx::Encoder<R, C>)
-    where
-        C: gfx::CommandBuffer<R>,
-    {
-        self.bundle.encode(encoder);
-    }
-}
+type DepthFormat = gfx::format::DepthStencil;
+type Resources = gfx_device_gl::Resources;
 
-gfx_constant_struct!(LightingPropertiesStatic {
-    window_size_in_pixels: [f32; 2] = "u_WindowSizeInPixels",
-});
-
-gfx_constant_struct!(LightingProperties {
-    eye_position_in_pixels: [f32; 2] = "u_EyePositionInPixels",
-});
-
-gfx_pipeline!(lighting_pipe {
-    quad_corners: gfx::VertexBuffer<QuadCorners> = (),
-    properties: gfx::ConstantBuffer<LightingProperties> = "Properties",
-    properties_static: gfx::ConstantBuffer<LightingPropertiesStatic> = "PropertiesStatic",
-    in_colour: gfx::TextureSampler<View> = "t_Colour",
-    in_visibility: gfx::TextureSampler<View> = "t_Visibility",
-    out_colour: gfx::BlendTarget<ColourFormat> =
-        ("Target0", gfx::state::ColorMask::all(), gfx::preset::blend::ALPHA),
-});
-
-struct LightingRenderer<R: gfx::Resources> {
-    bundle: gfx::Bundle<R, lighting_pipe::Data<R>>,
-    visibility_srv: gfx::handle::ShaderResourceView<R, View>,
+/// Frames taking longer than this are treated as though they took this long, so a stall
+/// doesn't leave the simulation with a huge backlog of ticks to catch up on.
+const MAX_FRAME_SECONDS: f32 = 0.25;
+
+/// An asteroid hit shakes the screen in proportion to the asteroid's size
+const ASTEROID_HIT_TRAUMA_PER_PIXEL: f32 = 1. / 256.;
+/// Camera shake when the player collides with another entity, per unit of impulse
+const PLAYER_COLLISION_TRAUMA_PER_IMPULSE: f32 = 1. / 1024.;
+/// Camera shake when the player's ship is destroyed
+const PLAYER_DESTROYED_TRAUMA: f32 = 1.;
+
+const LIGHTING_SETTINGS: LightingSettings = LightingSettings {
+    ambient_light: [0.04, 0.04, 0.06],
+    falloff_exponent: 1.5,
+    light_source_radius_in_pixels: 8.,
+    num_shadow_samples: 4,
+    vision_cone: Some(VisionCone {
+        half_angle: 1.2,
+        range_in_pixels: 640.,
+        soft_edge_angle: 0.3,
+        soft_edge_in_pixels: 128.,
+    }),
+    remembered_brightness: 0.3,
+    remembered_saturation: 0.2,
+};
+
+/// Checking what the player can see is too slow to do every tick, and a little lag in
+/// remembering what's been seen isn't noticeable
+const EXPLORATION_INTERVAL_TICKS: u64 = 6;
+
+/// Without a vision cone to limit how far the player can see, exploration stops here
+const MAX_EXPLORATION_DISTANCE: f32 = 1024.;
+
+/// Relative to the working directory
+const DEFAULT_BINDINGS_PATH: &str = "bindings.toml";
+const USAGE: &str = "Usage: belt [--map PATH] [--bindings PATH] [--record PATH] \
+                     [--replay PATH] [--explored PATH]";
+
+const WINDOW_TITLE: &str = "belt";
+
+struct Args {
+    /// The built-in map is used if this isn't given
+    map_path: Option<String>,
+    bindings_path: String,
+    record_path: Option<String>,
+    replay_path: Option<String>,
+    /// Where the parts of the map the player has seen are loaded from at startup, if the
+    /// file exists, and saved to on exit
+    explored_path: Option<String>,
 }
 
-impl<R: gfx::Resources> LightingRenderer<R> {
-    pub fn new<F, C>(
-        colour_srv: gfx::handle::ShaderResourceView<R, View>,
-        visibility_srv: gfx::handle::ShaderResourceView<R, View>,
-        rtv: gfx::handle::RenderTargetView<R, ColourFormat>,
-        factory: &mut F,
-        encoder: &mut gfx::Encoder<R, C>,
-    ) -> Self
-    where
-        F: gfx::Factory<R> + gfx::traits::FactoryExt<R>,
-        C: gfx::CommandBuffer<R>,
-    {
-        let sampler = factory.create_sampler(gfx::texture::SamplerInfo::new(
-            gfx::texture::FilterMethod::Mipmap,
-            gfx::texture::WrapMode::Tile,
-        ));
-
-        let pso = factory
-            .create_pipeline_simple(
-                include_bytes!("shaders/lighting/shader.150.vert"),
-                include_bytes!("shaders/lighting/shader.150.frag"),
-                lighting_pipe::new(),
-            )
-            .expect("Failed to create pipeline");
-
-        let quad_corners_data = QUAD_COORDS
-            .iter()
-            .map(|v| QuadCorners {
-                corner_zero_to_one: *v,
-            })
-            .collect::<Vec<_>>();
-
-        let (quad_corners_buf, slice) = factory
-            .create_vertex_buffer_with_slice(&quad_corners_data, &QUAD_INDICES[..]);
-
-        let data = lighting_pipe::Data {
-            quad_corners: quad_corners_buf,
-            properties: factory.create_constant_buffer(1),
-            properties_static: factory.create_constant_buffer(1),
-            in_colour: (colour_srv, sampler.clone()),
-            in_visibility: (visibility_srv.clone(), sampler.clone()),
-            out_colour: rtv,
-        };
-        let bundle = gfx::pso::bundle::Bundle::new(slice, pso, data);
-        let (window_width, window_height, _, _) = bundle.data.out_colour.get_dimensions();
-        let properties_static = LightingPropertiesStatic {
-            window_size_in_pixels: [window_width as f32, window_height as f32],
-        };
-        let properties = LightingProperties {
-            eye_position_in_pixels: [0., 0.],
-        };
-        encoder
-            .update_constant_buffer(&bundle.data.properties_static, &properties_static);
-        encoder.update_constant_buffer(&bundle.data.properties, &properties);
-        Self {
-            bundle,
-            visibility_srv,
+fn parse_args() -> Result<Args, String> {
+    let mut args = env::args().skip(1);
+    let mut map_path = None;
+    let mut bindings_path = DEFAULT_BINDINGS_PATH.to_string();
+    let mut record_path = None;
+    let mut replay_path = None;
+    let mut explored_path = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--map" => map_path = Some(args.next().ok_or("missing PATH")?),
+            "--bindings" => bindings_path = args.next().ok_or("missing PATH")?,
+            "--record" => record_path = Some(args.next().ok_or("missing PATH")?),
+            "--replay" => replay_path = Some(args.next().ok_or("missing PATH")?),
+            "--explored" => explored_path = Some(args.next().ok_or("missing PATH")?),
+            other => return Err(format!("unexpected argument \"{}\"", other)),
         }
     }
-
-    fn update<C>(&self, eye_position: Vector2<f32>, encoder: &mut gfx::Encoder<R, C>)
-    where
-        C: gfx::CommandBuffer<R>,
-    {
-        let properties = LightingProperties {
-            eye_position_in_pixels: eye_position.into(),
-        };
-        encoder.update_constant_buffer(&self.bundle.data.properties, &properties);
-    }
-
-    fn generate_mipmap<C>(&self, encoder: &mut gfx::Encoder<R, C>)
-    where
-        C: gfx::CommandBuffer<R>,
-    {
-        encoder.generate_mipmap(&self.visibility_srv);
-    }
-
-    fn encode<C>(&self, encoder: &mut gfx::Encoder<R, C>)
-    where
-        C: gfx::CommandBuffer<R>,
-    {
-        self.bundle.encode(encoder);
-    }
-}
-
-gfx_constant_struct!(MapProperties {
-    output_size_in_pixels: [f32; 2] = "u_OutputSizeInPixels",
-});
-
-gfx_pipeline!(map_pipe {
-    quad_corners: gfx::VertexBuffer<QuadCorners> = (),
-    properties: gfx::ConstantBuffer<MapProperties> = "Properties",
-    image: gfx::TextureSampler<View> = "t_Image",
-    out_visibility: gfx::BlendTarget<ColourFormat> =
-        ("TargetVisibility", gfx::state::ColorMask::all(), gfx::preset::blend::ALPHA),
-    out_colour: gfx::BlendTarget<ColourFormat> =
-        ("TargetColour", gfx::state::ColorMask::all(), gfx::preset::blend::ALPHA),
-});
-
-struct MapRenderer<R: gfx::Resources> {
-    bundle: gfx::Bundle<R, map_pipe::Data<R>>,
-}
-
-impl<R: gfx::Resources> MapRenderer<R> {
-    pub fn new<F, C>(
-        colour_rtv: gfx::handle::RenderTargetView<R, ColourFormat>,
-        visibility_rtv: gfx::handle::RenderTargetView<R, ColourFormat>,
-        factory: &mut F,
-        encoder: &mut gfx::Encoder<R, C>,
-    ) -> Self
-    where
-        F: gfx::Factory<R> + gfx::traits::FactoryExt<R>,
-        C: gfx::CommandBuffer<R>,
-    {
-        let image = image::load_from_memory(include_bytes!("images/map.png"))
-            .expect("Failed to decode image")
-            .to_rgba();
-
-        let (image_width, image_height) = image.dimensions();
-        let tex_kind = gfx::texture::Kind::D2(
-            image_width as u16,
-            image_height as u16,
-            gfx::texture::AaMode::Single,
-        );
-        let tex_mipmap = gfx::texture::Mipmap::Allocated;
-        let (_, texture_srv) = factory
-            .create_texture_immutable_u8::<ColourFormat>(tex_kind, tex_mipmap, &[&image])
-            .expect("failed to create texture");
-        let sampler_info = gfx::texture::SamplerInfo {
-            filter: gfx::texture::FilterMethod::Trilinear,
-            wrap_mode: (
-                gfx::texture::WrapMode::Tile,
-                gfx::texture::WrapMode::Tile,
-                gfx::texture::WrapMode::Tile,
-            ),
-            lod_bias: gfx::texture::Lod::from(0.),
-            lod_range: (gfx::texture::Lod::from(0.), gfx::texture::Lod::from(100.)),
-            comparison: Some(gfx::state::Comparison::Equal),
-            border: gfx::texture::PackedColor(0),
-        };
-        let sampler = factory.create_sampler(sampler_info);
-
-        let pso = factory
-            .create_pipeline_simple(
-                include_bytes!("shaders/map/shader.150.vert"),
-                include_bytes!("shaders/map/shader.150.frag"),
-                map_pipe::new(),
-            )
-            .expect("Failed to create pipeline");
-
-        let quad_corners_data = QUAD_COORDS
-            .iter()
-            .map(|v| QuadCorners {
-                corner_zero_to_one: *v,
-            })
-            .collect::<Vec<_>>();
-
-        let (quad_corners_buf, slice) = factory
-            .create_vertex_buffer_with_slice(&quad_corners_data, &QUAD_INDICES[..]);
-
-        let data = map_pipe::Data {
-            quad_corners: quad_corners_buf,
-            properties: factory.create_constant_buffer(1),
-            image: (texture_srv, sampler),
-            out_visibility: visibility_rtv,
-            out_colour: colour_rtv,
-        };
-        let bundle = gfx::pso::bundle::Bundle::new(slice, pso, data);
-        let (window_width, window_height, _, _) = bundle.data.out_colour.get_dimensions();
-        let properties = MapProperties {
-            output_size_in_pixels: [window_width as f32, window_height as f32],
-        };
-        encoder.update_constant_buffer(&bundle.data.properties, &properties);
-        Self { bundle }
-    }
-
-    fn encode<C>(&self, encoder: &mut gfx::Encoder<R, C>)
-    where
-        C: gfx::CommandBuffer<R>,
-    {
-        self.bundle.encode(encoder);
-    }
-}
-
-gfx_vertex_struct!(QuadCorners {
-    corner_zero_to_one: [f32; 2] = "a_CornerZeroToOne",
-});
-
-gfx_vertex_struct!(QuadInstance {
-    position_of_centre_in_pixels: [f32; 2] = "i_PositionOfCentreInPixels",
-    dimensions_in_pixels: [f32; 2] = "i_DimensionsInPixels",
-    facing_vector: [f32; 2] = "i_FacingVector",
-    sprite_position_of_top_left_in_pixels: [f32; 2] = "i_SpritePositionOfTopLeftInPixels",
-    sprite_dimensions_in_pixels: [f32; 2] = "i_SpriteDimensionsInPixels",
-    is_player: f32 = "i_IsPlayer",
-});
-
-gfx_constant_struct!(QuadProperties {
-    window_size_in_pixels: [f32; 2] = "u_WindowSizeInPixels",
-    sprite_sheet_size_in_pixels: [f32; 2] = "u_SpriteSheetSizeInPixels",
-    sprite_scale: f32 = "u_SpriteScale",
-});
-
-gfx_pipeline!(quad_pipe {
-    quad_corners: gfx::VertexBuffer<QuadCorners> = (),
-    quad_instances: gfx::InstanceBuffer<QuadInstance> = (),
-    properties: gfx::ConstantBuffer<QuadProperties> = "Properties",
-    sprite_sheet: gfx::TextureSampler<View> = "t_SpriteSheet",
-    out_visibility: gfx::BlendTarget<ColourFormat> =
-        ("TargetVisibility", gfx::state::ColorMask::all(), gfx::preset::blend::ALPHA),
-    out_colour: gfx::BlendTarget<ColourFormat> =
-        ("TargetColour", gfx::state::ColorMask::all(), gfx::preset::blend::ALPHA),
-});
-
-struct QuadRenderer<R: gfx::Resources> {
-    bundle: gfx::Bundle<R, quad_pipe::Data<R>>,
-    num_quads: usize,
-    quad_instances_upload: gfx::handle::Buffer<R, QuadInstance>,
-}
-
-impl<R: gfx::Resources> QuadRenderer<R> {
-    pub fn new<F, C>(
-        colour_rtv: gfx::handle::RenderTargetView<R, ColourFormat>,
-        visibility_rtv: gfx::handle::RenderTargetView<R, ColourFormat>,
-        factory: &mut F,
-        encoder: &mut gfx::Encoder<R, C>,
-    ) -> Self
-    where
-        F: gfx::Factory<R> + gfx::traits::FactoryExt<R>,
-        C: gfx::CommandBuffer<R>,
-    {
-        let sprite_sheet_max = (14, 26);
-        let sprite_scale = 1;
-
-        let mut image = image::load_from_memory(include_bytes!("images/sprites.png"))
-            .expect("Failed to decode image");
-        let image = image.crop(0, 0, sprite_sheet_max.0, sprite_sheet_max.1);
-        let image = image
-            .resize(
-                sprite_scale * image.width(),
-                sprite_scale * image.height(),
-                image::FilterType::Nearest,
-            )
-            .to_rgba();
-        let (image_width, image_height) = image.dimensions();
-        let tex_kind = gfx::texture::Kind::D2(
-            image_width as u16,
-            image_height as u16,
-            gfx::texture::AaMode::Single,
-        );
-        let tex_mipmap = gfx::texture::Mipmap::Allocated;
-        let (_, texture_srv) = factory
-            .create_texture_immutable_u8::<ColourFormat>(tex_kind, tex_mipmap, &[&image])
-            .expect("failed to create texture");
-        let mut info = gfx::texture::SamplerInfo::new(
-            gfx::texture::FilterMethod::Scale,
-            gfx::texture::WrapMode::Border,
-        );
-        info.border = gfx::texture::PackedColor(0);
-        let sampler = factory.create_sampler(info);
-
-        encoder.generate_mipmap(&texture_srv);
-
-        let pso = factory
-            .create_pipeline_simple(
-                include_bytes!("shaders/quad/shader.150.vert"),
-                include_bytes!("shaders/quad/shader.150.frag"),
-                quad_pipe::new(),
-            )
-            .expect("Failed to create pipeline");
-
-        let quad_corners_data = QUAD_COORDS
-            .iter()
-            .map(|v| QuadCorners {
-                corner_zero_to_one: *v,
-            })
-            .collect::<Vec<_>>();
-
-        let (quad_corners_buf, slice) = factory
-            .create_vertex_buffer_with_slice(&quad_corners_data, &QUAD_INDICES[..]);
-
-        let data = quad_pipe::Data {
-            quad_corners: quad_corners_buf,
-            quad_instances: create_instance_buffer(MAX_NUM_QUADS, factory)
-                .expect("Failed to create instance buffer"),
-            properties: factory.create_constant_buffer(1),
-            sprite_sheet: (texture_srv, sampler),
-            out_colour: colour_rtv,
-            out_visibility: visibility_rtv,
-        };
-        let bundle = gfx::pso::bundle::Bundle::new(slice, pso, data);
-        let (window_width, window_height, _, _) = bundle.data.out_colour.get_dimensions();
-        let properties = QuadProperties {
-            window_size_in_pixels: [window_width as f32, window_height as f32],
-            sprite_sheet_size_in_pixels: [image_width as f32, image_height as f32],
-            sprite_scale: sprite_scale as f32,
-        };
-
-        let quad_instances_upload: gfx::handle::Buffer<R, QuadInstance> = factory
-            .create_upload_buffer(MAX_NUM_QUADS)
-            .expect("Failed to create instance upload buffer");
-        encoder.update_constant_buffer(&bundle.data.properties, &properties);
-
-        Self {
-            bundle,
-            num_quads: 0,
-            quad_instances_upload,
-        }
-    }
-
-    fn update<'a, F, I>(&mut self, to_render: I, factory: &mut F)
-    where
-        F: gfx::Factory<R> + gfx::traits::FactoryExt<R>,
-        I: IntoIterator<Item = ToRender<'a>>,
-    {
-        let mut quad_instance_writer = factory
-            .write_mapping(&self.quad_instances_upload)
-            .expect("Failed to map upload buffer");
-        self.num_quads = to_render
-            .into_iter()
-            .zip(quad_instance_writer.iter_mut())
-            .fold(0, |count, (to_render, writer)| {
-                writer.position_of_centre_in_pixels =
-                    to_render.physics.centre_position.into();
-                writer.dimensions_in_pixels =
-                    to_render.physics.bounding_dimensions.into();
-                writer.facing_vector = to_render.physics.facing.into();
-                writer.sprite_position_of_top_left_in_pixels =
-                    to_render.graphics.sprite_position_of_top_left_in_pixels;
-                writer.sprite_dimensions_in_pixels =
-                    to_render.graphics.sprite_dimensions_in_pixels;
-                writer.is_player = (to_render.is_player as u8) as f32;
-                count + 1
-            });
-        self.bundle.slice.instances = Some((self.num_quads as u32, 0));
-    }
-
-    fn encode<C>(&self, encoder: &mut gfx::Encoder<R, C>)
-    where
-        C: gfx::CommandBuffer<R>,
-    {
-        encoder
-            .copy_buffer(
-                &self.quad_instances_upload,
-                &self.bundle.data.quad_instances,
-                0,
-                0,
-                self.num_quads,
-            )
-            .expect("Failed to copy instances");
-        self.bundle.encode(encoder);
-    }
-}
-
-fn create_instance_buffer<R, F, T>(
-    size: usize,
-    factory: &mut F,
-) -> Result<gfx::handle::Buffer<R, T>, gfx::buffer::CreationError>
-where
-    R: gfx::Resources,
-    F: gfx::Factory<R> + gfx::traits::FactoryExt<R>,
-{
-    factory.create_buffer(
-        size,
-        gfx::buffer::Role::Vertex,
-        gfx::memory::Usage::Data,
-        gfx::memory::Bind::TRANSFER_DST,
-    )
+    Ok(Args {
+        map_path,
+        bindings_path,
+        record_path,
+        replay_path,
+        explored_path,
+    })
 }
 
 enum ExternalEvent {
     Quit,
+    Resize(u32, u32),
+    RebindingFinished,
 }
 
 fn update_input_model(
     input_model: &mut InputModel,
     events_loop: &mut glutin::EventsLoop,
     gilrs: &mut gilrs::Gilrs,
-) -> Option<ExternalEvent> {
-    let mut external_event = None;
+    bindings: &mut Bindings,
+    rebinding: &mut Option<Rebinding>,
+) -> Vec<ExternalEvent> {
+    let mut external_events = Vec::new();
     input_model.progress_buttons();
     while let Some(event) = gilrs.next_event() {
-        match event.event {
-            gilrs::EventType::AxisChanged(axis, value, _) => match axis {
-                gilrs::ev::Axis::LeftStickX => input_model.set_aim_x(value),
-                gilrs::ev::Axis::LeftStickY => input_model.set_aim_y(-value),
-                gilrs::ev::Axis::Unknown => input_model.set_thrust((value + 1.) / 2.),
-                _ => (),
-            },
-            gilrs::EventType::ButtonPressed(button, _) => match button {
-                gilrs::ev::Button::DPadUp => input_model.set_aim_y(1.),
-                gilrs::ev::Button::DPadDown => input_model.set_aim_y(-1.),
-                gilrs::ev::Button::DPadLeft => input_model.set_aim_x(-1.),
-                gilrs::ev::Button::DPadRight => input_model.set_aim_x(1.),
-                gilrs::ev::Button::RightTrigger => input_model.press_shoot(),
-                gilrs::ev::Button::South => input_model.release_shoot(),
-                _ => (),
-            },
-            gilrs::EventType::ButtonChanged(button, value, _) => match button {
-                gilrs::ev::Button::DPadUp => input_model.set_aim_y(value),
-                gilrs::ev::Button::DPadDown => input_model.set_aim_y(-value),
-                gilrs::ev::Button::DPadLeft => input_model.set_aim_x(-value),
-                gilrs::ev::Button::DPadRight => input_model.set_aim_x(value),
-                _ => (),
-            },
-            gilrs::EventType::ButtonReleased(button, _) => match button {
-                gilrs::ev::Button::DPadUp | gilrs::ev::Button::DPadDown => {
-                    input_model.set_aim_y(0.)
-                }
-                gilrs::ev::Button::DPadLeft | gilrs::ev::Button::DPadRight => {
-                    input_model.set_aim_x(0.)
+        let gamepad_name = gilrs[event.id].name().to_string();
+        let finished_rebinding =
+            match rebinding.as_mut() {
+                Some(rebinding) => match event.event {
+                    gilrs::EventType::ButtonPressed(button, _) => {
+                        rebinding.gamepad_button(&gamepad_name, button, bindings)
+                    }
+                    _ => false,
+                },
+                None => {
+                    match event.event {
+                        gilrs::EventType::AxisChanged(axis, value, _) => {
+                            bindings.gamepad_axis(&gamepad_name, axis, value, input_model)
+                        }
+                        gilrs::EventType::ButtonPressed(button, _) => bindings
+                            .gamepad_button(&gamepad_name, button, 1., input_model),
+                        gilrs::EventType::ButtonChanged(button, value, _) => bindings
+                            .gamepad_button(&gamepad_name, button, value, input_model),
+                        gilrs::EventType::ButtonReleased(button, _) => bindings
+                            .gamepad_button(&gamepad_name, button, 0., input_model),
+                        _ => (),
+                    }
+                    false
                 }
-                gilrs::ev::Button::RightTrigger => input_model.release_shoot(),
-                gilrs::ev::Button::South => input_model.release_shoot(),
-                _ => (),
-            },
-            _ => (),
+            };
+        if finished_rebinding {
+            *rebinding = None;
+            external_events.push(ExternalEvent::RebindingFinished);
         }
     }
 
-    events_loop.poll_events(|event| match event {
-        glutin::Event::WindowEvent { event, .. } => match event {
-            glutin::WindowEvent::CloseRequested => {
-                external_event = Some(ExternalEvent::Quit);
-            }
-            glutin::WindowEvent::KeyboardInput { input, .. } => {
-                if let Some(virtual_keycode) = input.virtual_keycode {
-                    match input.state {
-                        glutin::ElementState::Pressed => match virtual_keycode {
-                            glutin::VirtualKeyCode::W => input_model.set_aim_y(-1.),
-                            glutin::VirtualKeyCode::S => input_model.set_aim_y(1.),
-                            glutin::VirtualKeyCode::A => input_model.set_aim_x(-1.),
-                            glutin::VirtualKeyCode::D => input_model.set_aim_x(1.),
-                            glutin::VirtualKeyCode::Comma => input_model.set_aim_y(-1.),
-                            glutin::VirtualKeyCode::O => input_model.set_aim_y(1.),
-                            glutin::VirtualKeyCode::E => input_model.set_aim_x(1.),
-                            glutin::VirtualKeyCode::Return => input_model.press_shoot(),
-                            glutin::VirtualKeyCode::Space => input_model.set_thrust(1.),
-                            _ => (),
-                        },
-                        glutin::ElementState::Released => match virtual_keycode {
-                            glutin::VirtualKeyCode::W => input_model.set_aim_y(0.),
-                            glutin::VirtualKeyCode::S => input_model.set_aim_y(0.),
-                            glutin::VirtualKeyCode::A => input_model.set_aim_x(0.),
-                            glutin::VirtualKeyCode::D => input_model.set_aim_x(0.),
-                            glutin::VirtualKeyCode::Comma => input_model.set_aim_y(0.),
-                            glutin::VirtualKeyCode::O => input_model.set_aim_y(0.),
-                            glutin::VirtualKeyCode::E => input_model.set_aim_x(0.),
-                            glutin::VirtualKeyCode::Return => input_model.release_shoot(),
-                            glutin::VirtualKeyCode::Space => input_model.set_thrust(0.),
-                            _ => (),
-                        },
+    events_loop.poll_events(|event| {
+        if let glutin::Event::WindowEvent { event, .. } = event {
+            match event {
+                glutin::WindowEvent::CloseRequested => {
+                    external_events.push(ExternalEvent::Quit);
+                }
+                glutin::WindowEvent::Resized(width, height) => {
+                    external_events.push(ExternalEvent::Resize(width, height));
+                }
+                glutin::WindowEvent::KeyboardInput { input, .. } => {
+                    if let Some(virtual_keycode) = input.virtual_keycode {
+                        let pressed = input.state == glutin::ElementState::Pressed;
+                        let finished_rebinding = match rebinding.as_mut() {
+                            Some(rebinding) => {
+                                pressed && rebinding.key(virtual_keycode, bindings)
+                            }
+                            None => {
+                                if pressed && virtual_keycode == REBIND_KEY {
+                                    *input_model = InputModel::default();
+                                    *rebinding = Some(Rebinding::new());
+                                } else {
+                                    bindings.key(virtual_keycode, pressed, input_model);
+                                }
+                                false
+                            }
+                        };
+                        if finished_rebinding {
+                            *rebinding = None;
+                            external_events.push(ExternalEvent::RebindingFinished);
+                        }
                     }
                 }
+                _ => (),
             }
-            _ => (),
-        },
-        _ => (),
+        }
     });
 
-    external_event
+    external_events
 }
 
 fn main() {
-    let (width, height) = (1024, 1024);
+    let args = parse_args().unwrap_or_else(|message| {
+        eprintln!("{}\n{}", message, USAGE);
+        process::exit(1);
+    });
+
+    let map_name = args
+        .map_path
+        .as_ref()
+        .map_or("(built-in)", |path| path.as_str());
+    let mut map_file = args.map_path.as_ref().map(MapFile::new);
+    let map_image = match map_file.as_mut() {
+        Some(map_file) => map_file.load(),
+        None => map::load_builtin(),
+    }
+    .unwrap_or_else(|error| {
+        eprintln!("Failed to load map {}: {}", map_name, error);
+        process::exit(1);
+    });
+    let sprite_atlas = SpriteAtlas::builtin().unwrap_or_else(|error| {
+        eprintln!("Failed to load sprite atlas: {}", error);
+        process::exit(1);
+    });
+
     let builder = glutin::WindowBuilder::new()
-        .with_dimensions(width, height)
-        .with_min_dimensions(width, height)
-        .with_max_dimensions(width, height);
+        .with_title(WINDOW_TITLE)
+        .with_dimensions(1024, 1024);
     let mut events_loop = glutin::EventsLoop::new();
     let context = glutin::ContextBuilder::new().with_vsync(true);
-    let (window, mut device, mut factory, rtv, dsv) = gfx_window_glutin::init::<
+    let (window, mut device, mut factory, mut rtv, mut dsv) = gfx_window_glutin::init::<
         ColourFormat,
         DepthFormat,
     >(builder, context, &events_loop);
@@ -628,309 +241,229 @@ fn main() {
     let mut encoder: gfx::Encoder<Resources, gfx_device_gl::CommandBuffer> =
         factory.create_command_buffer().into();
 
-    let (_, output_srv, output_rtv) = factory
-        .create_render_target(width as u16, height as u16)
-        .expect("Failed to create render target");
-
-    let (_, colour_srv, colour_rtv) = factory
-        .create_render_target(width as u16, height as u16)
-        .expect("Failed to create render target");
-
-    let tex_kind =
-        gfx::texture::Kind::D2(width as u16, height as u16, gfx::texture::AaMode::Single);
-
-    let cty = <<Format as gfx::format::Formatted>::Channel as gfx::format::ChannelTyped>::get_channel_type();
-
-    let visibility_tex: gfx::handle::Texture<Resources, Surface> = factory
-        .create_texture::<Surface>(
-            tex_kind,
-            tex_kind.get_num_levels(),
-            gfx::memory::Bind::SHADER_RESOURCE | gfx::memory::Bind::RENDER_TARGET,
-            gfx::memory::Usage::Data,
-            Some(cty),
-        )
-        .expect("Failed to create texture");
-
-    let visibility_srv: gfx::handle::ShaderResourceView<Resources, View> = factory
-        .view_texture_as_shader_resource::<Format>(
-            &visibility_tex,
-            (0, tex_kind.get_num_levels()),
-            gfx::format::Swizzle::new(),
-        )
-        .unwrap();
-
-    let visibility_rtv: gfx::handle::RenderTargetView<Resources, Format> = factory
-        .view_texture_as_render_target::<Format>(&visibility_tex, 0, None)
-        .unwrap();
-
-    let mut quad_renderer = QuadRenderer::new(
-        colour_rtv.clone(),
-        visibility_rtv.clone(),
-        &mut factory,
-        &mut encoder,
-    );
-
-    let map_renderer = MapRenderer::new(
-        colour_rtv.clone(),
-        visibility_rtv.clone(),
+    let mut renderer = Renderer::new(
+        &map_image,
+        &sprite_atlas,
+        LIGHTING_SETTINGS,
+        rtv.clone(),
         &mut factory,
         &mut encoder,
     );
 
-    let lighting_renderer = LightingRenderer::new(
-        colour_srv,
-        visibility_srv,
-        output_rtv,
-        &mut factory,
-        &mut encoder,
+    let mut gilrs = gilrs::Gilrs::new().unwrap();
+    let mut bindings = Bindings::load(&args.bindings_path).unwrap_or_else(|error| {
+        eprintln!("Failed to load bindings {}: {}", args.bindings_path, error);
+        process::exit(1);
+    });
+    let mut rebinding = None;
+    let mut window_title = WINDOW_TITLE.to_string();
+
+    let mut replay = args.replay_path.as_ref().map(|path| {
+        let replay = Replay::load(path).unwrap_or_else(|error| {
+            eprintln!("Failed to load replay {}: {}", path, error);
+            process::exit(1);
+        });
+        if replay.map_hash() != replay::map_hash(&map_image) {
+            eprintln!("Warning: replay {} was recorded on a different map", path);
+        }
+        replay
+    });
+    let seed = replay.as_ref().map_or_else(rand::random, Replay::seed);
+    let mut recorder = args.record_path.as_ref().map(|path| {
+        ReplayRecorder::create(path, seed, replay::map_hash(&map_image)).unwrap_or_else(
+            |error| {
+                eprintln!("Failed to create replay {}: {}", path, error);
+                process::exit(1);
+            },
+        )
+    });
+    // Changing the map part way through a run can't be reproduced from a replay
+    let reload_map = recorder.is_none() && replay.is_none();
+    let mut replay_desynced = false;
+
+    let mut game_state = GameState::new(&map_image, &sprite_atlas, seed);
+    let (window_width, window_height, _, _) = rtv.get_dimensions();
+    let mut camera = Camera::new(
+        game_state.player_info(1.).physics.centre_position,
+        vec2(window_width as f32, window_height as f32),
     );
 
-    let world_size = vec2(width as f32, height as f32);
-
-    let output_renderer = OutputRenderer::new(
-        output_srv,
-        world_size,
-        rtv.clone(),
-        &mut factory,
-        &mut encoder,
+    let mut explored_map = args.explored_path.as_ref().map_or_else(
+        || ExploredMap::new(game_state.world_size()),
+        |path| match ExploredMap::load(path, game_state.world_size()) {
+            Ok(explored_map) => explored_map,
+            Err(ExploredMapError::Io(ref error))
+                if error.kind() == io::ErrorKind::NotFound =>
+            {
+                ExploredMap::new(game_state.world_size())
+            }
+            Err(error) => {
+                eprintln!("Failed to load explored map {}: {}", path, error);
+                ExploredMap::new(game_state.world_size())
+            }
+        },
     );
+    renderer.set_explored(&explored_map, &mut factory, &mut encoder);
+    let mut explored_map_changed = false;
 
-    let mut gilrs = gilrs::Gilrs::new().unwrap();
-
-    let mut game_state = GameState::new();
     let mut input_model = InputModel::default();
+    let mut last_frame = Instant::now();
+    let mut time_since_last_tick = 0.;
+    let mut tick = 0;
     loop {
         encoder.clear(&rtv, [0.0, 0.0, 0.0, 1.0]);
         encoder.clear_depth(&dsv, 1.0);
-        match update_input_model(&mut input_model, &mut events_loop, &mut gilrs) {
-            Some(ExternalEvent::Quit) => break,
-            None => (),
-        }
-        game_state.update(&input_model);
-        quad_renderer.update(game_state.to_render(), &mut factory);
-        map_renderer.encode(&mut encoder);
-        quad_renderer.encode(&mut encoder);
-
-        lighting_renderer.generate_mipmap(&mut encoder);
-
-        let player_info = game_state.player_info();
-        let eye_position = player_info.physics.centre_position;
-        lighting_renderer.update(eye_position, &mut encoder);
-        lighting_renderer.encode(&mut encoder);
-        output_renderer.update(player_info, 4., &mut encoder);
-        output_renderer.encode(&mut encoder);
-
-        encoder.flush(&mut device);
-        window.swap_buffers().unwrap();
-        device.cleanup();
-    }
-}
-
-#[derive(Debug, Default)]
-struct ButtonState {
-    current: bool,
-    previous: bool,
-}
-
-impl ButtonState {
-    fn progress(&mut self) {
-        self.previous = self.current;
-    }
-    fn press(&mut self) {
-        self.current = true;
-    }
-    fn release(&mut self) {
-        self.current = false;
-    }
-}
-
-#[derive(Debug)]
-pub struct InputModel {
-    aim_vec: Vector2<f32>,
-    shoot: ButtonState,
-    thrust: f32,
-}
-
-impl Default for InputModel {
-    fn default() -> Self {
-        Self {
-            aim_vec: vec2(0., 0.),
-            shoot: ButtonState::default(),
-            thrust: 0.,
-        }
-    }
-}
-
-const ANALOG_THRESHOLD: f32 = 0.1;
-
-fn analog_threshold_value(v: f32) -> f32 {
-    if v.abs() > ANALOG_THRESHOLD {
-        v
-    } else {
-        0.
-    }
-}
-
-impl InputModel {
-    pub fn progress_buttons(&mut self) {
-        self.shoot.progress();
-    }
-    pub fn press_shoot(&mut self) {
-        self.shoot.press();
-    }
-    pub fn release_shoot(&mut self) {
-        self.shoot.release();
-    }
-    pub fn set_aim_x(&mut self, value: f32) {
-        self.aim_vec.x = analog_threshold_value(value);
-    }
-    pub fn set_aim_y(&mut self, value: f32) {
-        self.aim_vec.y = analog_threshold_value(value);
-    }
-    pub fn set_thrust(&mut self, value: f32) {
-        self.thrust = analog_threshold_value(value).max(0.);
-    }
-    fn aim_vector(&self) -> Option<Vector2<f32>> {
-        let magnitude2 = self.aim_vec.magnitude2();
-        if magnitude2 >= 1. {
-            Some(self.aim_vec.normalize())
-        } else {
-            const AIM_THRESHOLD2: f32 = 0.2;
-            if magnitude2 > AIM_THRESHOLD2 {
-                Some(self.aim_vec.normalize())
-            } else {
-                None
+        let external_events = update_input_model(
+            &mut input_model,
+            &mut events_loop,
+            &mut gilrs,
+            &mut bindings,
+            &mut rebinding,
+        );
+        for external_event in external_events {
+            match external_event {
+                ExternalEvent::Quit => {
+                    if let Some(recorder) = recorder {
+                        if let Err(error) = recorder.finish() {
+                            eprintln!("Failed to write replay: {}", error);
+                        }
+                    }
+                    if let Some(path) = args.explored_path.as_ref() {
+                        if let Err(error) = explored_map.save(path) {
+                            eprintln!("Failed to save explored map {}: {}", path, error);
+                        }
+                    }
+                    return;
+                }
+                ExternalEvent::Resize(width, height) => {
+                    window.resize(width, height);
+                    gfx_window_glutin::update_views(&window, &mut rtv, &mut dsv);
+                    renderer.resize(rtv.clone(), &mut factory, &mut encoder);
+                    camera.set_window_size(vec2(width as f32, height as f32));
+                }
+                ExternalEvent::RebindingFinished => {
+                    match bindings.save(&args.bindings_path) {
+                        Ok(()) => println!("Saved bindings to {}", args.bindings_path),
+                        Err(error) => eprintln!(
+                            "Failed to save bindings {}: {}",
+                            args.bindings_path, error
+                        ),
+                    }
+                }
             }
         }
-    }
-}
-
-type EntityId = u16;
-
-#[derive(Default)]
-struct EntityIdAllocator {
-    next: EntityId,
-}
-
-impl EntityIdAllocator {
-    fn allocate(&mut self) -> EntityId {
-        let id = self.next;
-        self.next += 1;
-        id
-    }
-}
-
-struct Physics {
-    centre_position: Vector2<f32>,
-    bounding_dimensions: Vector2<f32>,
-    velocity: Vector2<f32>,
-    facing: Vector2<f32>,
-}
-
-#[derive(Clone)]
-struct Graphics {
-    sprite_position_of_top_left_in_pixels: [f32; 2],
-    sprite_dimensions_in_pixels: [f32; 2],
-}
-
-pub struct GameState {
-    player_id: EntityId,
-    entity_id_allocator: EntityIdAllocator,
-    physics: FnvHashMap<EntityId, Physics>,
-    graphics: FnvHashMap<EntityId, Graphics>,
-}
-
-pub struct ToRender<'a> {
-    graphics: &'a Graphics,
-    physics: &'a Physics,
-    is_player: bool,
-}
-
-pub struct PlayerInfo<'a> {
-    graphics: &'a Graphics,
-    physics: &'a Physics,
-}
-
-impl GameState {
-    pub fn new() -> Self {
-        let mut entity_id_allocator = EntityIdAllocator::default();
-        let player_id = entity_id_allocator.allocate();
-        let mut game_state = Self {
-            player_id,
-            entity_id_allocator,
-            physics: Default::default(),
-            graphics: Default::default(),
-        };
-        game_state.physics.insert(
-            player_id,
-            Physics {
-                centre_position: vec2(266., 550.),
-                bounding_dimensions: vec2(32., 64.),
-                velocity: vec2(0., 0.),
-                facing: vec2(1., -1.).normalize(),
-            },
-        );
-        game_state.graphics.insert(
-            player_id,
-            Graphics {
-                sprite_position_of_top_left_in_pixels: [0., 0.],
-                sprite_dimensions_in_pixels: [14., 26.],
-            },
-        );
+        // The window has nowhere else to show text, so rebinding prompts go in its title
+        let title = rebinding
+            .as_ref()
+            .map_or_else(|| WINDOW_TITLE.to_string(), Rebinding::prompt);
+        if title != window_title {
+            window.set_title(&title);
+            window_title = title;
+        }
 
-        let asteroid_graphics = Graphics {
-            sprite_position_of_top_left_in_pixels: [32., 0.],
-            sprite_dimensions_in_pixels: [64., 64.],
+        let reloaded_map = match map_file.as_mut() {
+            Some(map_file) if reload_map => map_file.reload_if_changed(),
+            _ => None,
         };
-        let asteroid_physics = |centre_position: Vector2<f32>| Physics {
-            centre_position,
-            bounding_dimensions: vec2(64., 64.),
-            velocity: vec2(0., 0.),
-            facing: vec2(0., 1.),
-        };
-        {
-            let _add_asteroid = |centre_position| {
-                let id = game_state.entity_id_allocator.allocate();
-                game_state.graphics.insert(id, asteroid_graphics.clone());
-                game_state
-                    .physics
-                    .insert(id, asteroid_physics(centre_position));
-            };
-        }
-        game_state
-    }
-    pub fn to_render(&self) -> impl Iterator<Item = ToRender> {
-        self.physics.iter().filter_map(move |(id, physics)| {
-            self.graphics.get(id).map(|graphics| ToRender {
-                physics,
-                graphics,
-                is_player: *id == self.player_id,
-            })
-        })
-    }
-    pub fn update(&mut self, input_model: &InputModel) {
-        for physics in self.physics.values_mut() {
-            physics.centre_position += physics.velocity;
+        match reloaded_map {
+            Some(Ok(map_image)) => {
+                renderer.set_map(&map_image, &mut factory);
+                game_state.set_map(&map_image);
+                // What's been explored still applies if the map was only edited, but not
+                // if it changed size
+                let resized_explored_map = ExploredMap::new(game_state.world_size());
+                if resized_explored_map.size_in_pixels() != explored_map.size_in_pixels()
+                {
+                    explored_map = resized_explored_map;
+                    explored_map_changed = true;
+                }
+            }
+            Some(Err(error)) => {
+                eprintln!("Failed to reload map {}: {}", map_name, error);
+            }
+            None => (),
         }
 
-        if let Some(physics) = self.physics.get_mut(&self.player_id) {
-            if let Some(aim_vector) = input_model.aim_vector() {
-                physics.facing = aim_vector;
+        let now = Instant::now();
+        let frame_duration = now - last_frame;
+        last_frame = now;
+        let frame_seconds =
+            frame_duration.as_secs() as f32 + frame_duration.subsec_nanos() as f32 * 1e-9;
+        time_since_last_tick += frame_seconds.min(MAX_FRAME_SECONDS);
+        while time_since_last_tick >= TICK_SECONDS {
+            let result = replay::run_tick(
+                tick,
+                &mut game_state,
+                &mut input_model,
+                replay.as_ref(),
+                recorder.as_mut(),
+            );
+            // Once the replay runs out, the player takes over from where it left off
+            if replay.is_some() && !result.replayed {
+                println!("Replay finished after {} ticks", tick);
+                replay = None;
             }
-            const THRUST_MULTIPLIER: f32 = 0.2;
-            let next_velocity = physics.velocity
-                + physics.facing * input_model.thrust * THRUST_MULTIPLIER;
-            physics.velocity = next_velocity;
+            if result.desynced && !replay_desynced {
+                eprintln!("Replay desynced at tick {}", tick);
+                replay_desynced = true;
+            }
+            if let Err(error) = result.record_result {
+                eprintln!(
+                    "Failed to write replay, so recording has stopped: {}",
+                    error
+                );
+                recorder = None;
+            }
+            tick += 1;
+            for event in game_state.events() {
+                match *event {
+                    GameEvent::AsteroidHit { size, .. } => {
+                        camera.add_trauma(size * ASTEROID_HIT_TRAUMA_PER_PIXEL)
+                    }
+                    GameEvent::Collision {
+                        impulse,
+                        involves_player: true,
+                        ..
+                    } => camera.add_trauma(impulse * PLAYER_COLLISION_TRAUMA_PER_IMPULSE),
+                    GameEvent::Collision { .. } => (),
+                    GameEvent::Destroyed {
+                        is_player: true, ..
+                    } => camera.add_trauma(PLAYER_DESTROYED_TRAUMA),
+                    GameEvent::Destroyed { .. } => (),
+                }
+            }
+            let player_physics = game_state.player_info(1.).physics;
+            camera.update(
+                player_physics.centre_position,
+                player_physics.velocity,
+                game_state.world_size(),
+                &input_model,
+            );
+            if tick % EXPLORATION_INTERVAL_TICKS == 0 {
+                let vision_cone = LIGHTING_SETTINGS.vision_cone;
+                let max_distance = vision_cone
+                    .map_or(MAX_EXPLORATION_DISTANCE, |cone| cone.range_in_pixels);
+                explored_map_changed |=
+                    explored_map.update(&game_state, vision_cone.as_ref(), max_distance);
+            }
+            time_since_last_tick -= TICK_SECONDS;
         }
-    }
-    pub fn player_info(&self) -> PlayerInfo {
-        PlayerInfo {
-            physics: self.physics
-                .get(&self.player_id)
-                .expect("no player physics"),
-            graphics: self.graphics
-                .get(&self.player_id)
-                .expect("no player graphics"),
+        if explored_map_changed {
+            renderer.set_explored(&explored_map, &mut factory, &mut encoder);
+            explored_map_changed = false;
         }
+        let alpha = time_since_last_tick / TICK_SECONDS;
+
+        renderer.render(
+            &game_state,
+            camera.view(alpha),
+            alpha,
+            &mut factory,
+            &mut encoder,
+        );
+
+        encoder.flush(&mut device);
+        window.swap_buffers().unwrap();
+        device.cleanup();
     }
 }
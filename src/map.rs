@@ -0,0 +1,157 @@
+use image::{self, GenericImage, ImageError, Rgba, RgbaImage};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Built into the game so it runs without any files alongside it
+const BUILTIN_MAP: &[u8] = include_bytes!("images/map.png");
+
+/// The size of the coarsest cell the lighting shader samples. Maps are padded with opaque
+/// pixels to a multiple of this, so those cells never straddle the edge of the map.
+pub const ALIGNMENT: u32 = 16;
+
+/// Texture dimensions are limited to 16 bits, and maps must still fit once padded.
+const MAX_DIMENSION: u32 = u16::MAX as u32 / ALIGNMENT * ALIGNMENT;
+
+#[derive(Debug)]
+pub enum MapLoadError {
+    Io(io::Error),
+    Decode(ImageError),
+    Empty,
+    TooLarge { dimensions: (u32, u32) },
+}
+
+impl fmt::Display for MapLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MapLoadError::Io(error) => write!(f, "{}", error),
+            MapLoadError::Decode(error) => write!(f, "failed to decode image: {}", error),
+            MapLoadError::Empty => write!(f, "map has no pixels"),
+            MapLoadError::TooLarge { dimensions } => write!(
+                f,
+                "map is {}x{} pixels but each dimension can be at most {}",
+                dimensions.0, dimensions.1, MAX_DIMENSION
+            ),
+        }
+    }
+}
+
+/// Rejects maps which are empty or too large, and pads the rest to a multiple of
+/// `ALIGNMENT` in each dimension.
+fn validate(image: RgbaImage) -> Result<RgbaImage, MapLoadError> {
+    let dimensions = image.dimensions();
+    if dimensions.0 == 0 || dimensions.1 == 0 {
+        return Err(MapLoadError::Empty);
+    }
+    if dimensions.0 > MAX_DIMENSION || dimensions.1 > MAX_DIMENSION {
+        return Err(MapLoadError::TooLarge { dimensions });
+    }
+    let padded = |size: u32| size.div_ceil(ALIGNMENT) * ALIGNMENT;
+    let (width, height) = (padded(dimensions.0), padded(dimensions.1));
+    if (width, height) == dimensions {
+        return Ok(image);
+    }
+    let mut padded_image = RgbaImage::from_pixel(width, height, Rgba([0, 0, 0, 255]));
+    padded_image.copy_from(&image, 0, 0);
+    Ok(padded_image)
+}
+
+fn decode(bytes: &[u8]) -> Result<RgbaImage, MapLoadError> {
+    let image = image::load_from_memory(bytes)
+        .map_err(MapLoadError::Decode)?
+        .to_rgba();
+    validate(image)
+}
+
+/// Loads the map built into the game, for when no map file is given.
+pub fn load_builtin() -> Result<RgbaImage, MapLoadError> {
+    decode(BUILTIN_MAP)
+}
+
+/// A map image on disk, which can be reloaded whenever the file changes.
+pub struct MapFile {
+    path: PathBuf,
+    last_modified: Option<SystemTime>,
+}
+
+impl MapFile {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            last_modified: None,
+        }
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        fs::metadata(&self.path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+
+    pub fn load(&mut self) -> Result<RgbaImage, MapLoadError> {
+        self.last_modified = self.modified();
+        let bytes = fs::read(&self.path).map_err(MapLoadError::Io)?;
+        decode(&bytes)
+    }
+
+    /// Loads the map again if the file has been modified since it was last loaded. A failed
+    /// load is only reported once, rather than on every call until the file changes again.
+    pub fn reload_if_changed(&mut self) -> Option<Result<RgbaImage, MapLoadError>> {
+        let modified = self.modified();
+        if modified.is_none() || modified == self.last_modified {
+            None
+        } else {
+            Some(self.load())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: Rgba<u8> = Rgba {
+        data: [255, 255, 255, 255],
+    };
+
+    #[test]
+    fn aligned_maps_are_unchanged() {
+        let image = RgbaImage::from_pixel(32, 48, WHITE);
+        let validated = validate(image.clone()).unwrap();
+        assert_eq!(validated.dimensions(), image.dimensions());
+        assert_eq!(validated.into_raw(), image.into_raw());
+    }
+
+    #[test]
+    fn unaligned_maps_are_padded_with_opaque_pixels() {
+        let padded = validate(RgbaImage::from_pixel(1000, 17, WHITE)).unwrap();
+        assert_eq!(padded.dimensions(), (1008, 32));
+        assert_eq!(*padded.get_pixel(999, 16), WHITE);
+        assert_eq!(*padded.get_pixel(1000, 0), Rgba([0, 0, 0, 255]));
+        assert_eq!(*padded.get_pixel(0, 17), Rgba([0, 0, 0, 255]));
+    }
+
+    #[test]
+    fn empty_and_oversized_maps_are_rejected() {
+        match validate(RgbaImage::new(0, 16)) {
+            Err(MapLoadError::Empty) => (),
+            other => panic!(
+                "expected Empty, got {:?}",
+                other.map(|image| image.dimensions())
+            ),
+        }
+        match validate(RgbaImage::new(MAX_DIMENSION + 1, 1)) {
+            Err(MapLoadError::TooLarge { dimensions }) => {
+                assert_eq!(dimensions, (MAX_DIMENSION + 1, 1))
+            }
+            other => panic!(
+                "expected TooLarge, got {:?}",
+                other.map(|image| image.dimensions())
+            ),
+        }
+        assert!(validate(RgbaImage::new(MAX_DIMENSION, 1)).is_ok());
+        assert!(MAX_DIMENSION <= u16::MAX as u32);
+    }
+}
@@ -0,0 +1,1442 @@
+use gfx;
+use image::{self, GenericImage};
+
+use cgmath::{InnerSpace, Vector2, vec2};
+
+use belt::camera::{CameraView, MIN_ZOOM};
+use belt::exploration::ExploredMap;
+use belt::game_state::{GameState, RenderLayer, ToLight, ToRender};
+use belt::map;
+use belt::sprite_atlas::SpriteAtlas;
+use belt::visibility::VisionCone;
+
+pub type ColourFormat = gfx::format::Srgba8;
+
+type Format = (gfx::format::R8_G8_B8_A8, gfx::format::Srgb);
+type Surface = <Format as gfx::format::Formatted>::Surface;
+type View = <Format as gfx::format::Formatted>::View;
+
+const QUAD_INDICES: [u16; 6] = [0, 1, 2, 2, 3, 0];
+const QUAD_COORDS: [[f32; 2]; 4] = [[0.0, 0.0], [0.0, 1.0], [1.0, 1.0], [1.0, 0.0]];
+
+/// The map is split into square textures of this size, so it isn't limited by the maximum
+/// texture size.
+const MAP_TILE_SIZE: u32 = 1024;
+
+/// The part of the world drawn each frame is aligned to the size of the coarsest cell the
+/// lighting shader samples, so its mipmaps line up with those of the whole world.
+const REGION_ALIGNMENT: f32 = map::ALIGNMENT as f32;
+
+/// The region extends this far beyond the view on every side, so lights just out of view
+/// still cast the right shadows into it. Shadows from further out are only traced as far as
+/// the region's edge. Must be a multiple of `REGION_ALIGNMENT`.
+const REGION_MARGIN: f32 = 256.;
+
+/// Room for this many instances in each render layer is allocated up front. Layers with
+/// more instances have their buffers grown to fit.
+const INITIAL_NUM_QUADS: usize = 1024;
+
+/// Must match the array size in the lighting shader. Beyond this, the lights furthest from
+/// the camera are left out.
+const MAX_NUM_LIGHTS: usize = 32;
+
+// Constant buffers are copied to the GPU byte for byte, so their fields have to stay in
+// the order they're declared to line up with the std140 layout of the uniform blocks
+gfx_defines! {
+    #[repr(C)]
+    constant OutputProperties {
+        camera_position_in_pixels: [f32; 2] = "u_CameraPositionInPixels",
+        zoom: f32 = "u_Zoom",
+    }
+}
+
+gfx_defines! {
+    #[repr(C)]
+    constant OutputPropertiesStatic {
+        window_size_in_pixels: [f32; 2] = "u_WindowSizeInPixels",
+        input_size_in_pixels: [f32; 2] = "u_InputSizeInPixels",
+    }
+}
+
+gfx_pipeline!(output_pipe {
+    quad_corners: gfx::VertexBuffer<QuadCorners> = (),
+    properties: gfx::ConstantBuffer<OutputProperties> = "Properties",
+    properties_static: gfx::ConstantBuffer<OutputPropertiesStatic> = "PropertiesStatic",
+    in_colour: gfx::TextureSampler<View> = "t_Colour",
+    out_colour: gfx::BlendTarget<ColourFormat> =
+        ("Target0", gfx::state::ColorMask::all(), gfx::preset::blend::ALPHA),
+});
+struct OutputRenderer<R: gfx::Resources> {
+    bundle: gfx::Bundle<R, output_pipe::Data<R>>,
+}
+
+impl<R: gfx::Resources> OutputRenderer<R> {
+    pub fn new<F, C>(
+        colour_srv: gfx::handle::ShaderResourceView<R, View>,
+        srv_size: Vector2<f32>,
+        rtv: gfx::handle::RenderTargetView<R, ColourFormat>,
+        factory: &mut F,
+        encoder: &mut gfx::Encoder<R, C>,
+    ) -> Self
+    where
+        F: gfx::Factory<R> + gfx::traits::FactoryExt<R>,
+        C: gfx::CommandBuffer<R>,
+    {
+        let sampler = factory.create_sampler(gfx::texture::SamplerInfo::new(
+            gfx::texture::FilterMethod::Scale,
+            gfx::texture::WrapMode::Border,
+        ));
+
+        let pso = factory
+            .create_pipeline_simple(
+                include_bytes!("shaders/output/shader.150.vert"),
+                include_bytes!("shaders/output/shader.150.frag"),
+                output_pipe::new(),
+            )
+            .expect("Failed to create pipeline");
+
+        let quad_corners_data = QUAD_COORDS
+            .iter()
+            .map(|v| QuadCorners {
+                corner_zero_to_one: *v,
+            })
+            .collect::<Vec<_>>();
+
+        let (quad_corners_buf, slice) = factory
+            .create_vertex_buffer_with_slice(&quad_corners_data, &QUAD_INDICES[..]);
+
+        let data = output_pipe::Data {
+            quad_corners: quad_corners_buf,
+            properties: factory.create_constant_buffer(1),
+            properties_static: factory.create_constant_buffer(1),
+            in_colour: (colour_srv, sampler.clone()),
+            out_colour: rtv,
+        };
+        let bundle = gfx::pso::bundle::Bundle::new(slice, pso, data);
+        let output_renderer = Self { bundle };
+        output_renderer.update_properties_static(srv_size, encoder);
+        output_renderer
+    }
+
+    fn update_properties_static<C>(
+        &self,
+        srv_size: Vector2<f32>,
+        encoder: &mut gfx::Encoder<R, C>,
+    ) where
+        C: gfx::CommandBuffer<R>,
+    {
+        let (window_width, window_height, _, _) =
+            self.bundle.data.out_colour.get_dimensions();
+        let properties_static = OutputPropertiesStatic {
+            window_size_in_pixels: [window_width as f32, window_height as f32],
+            input_size_in_pixels: srv_size.into(),
+        };
+        encoder.update_constant_buffer(
+            &self.bundle.data.properties_static,
+            &properties_static,
+        );
+    }
+
+    fn set_targets<C>(
+        &mut self,
+        colour_srv: gfx::handle::ShaderResourceView<R, View>,
+        srv_size: Vector2<f32>,
+        rtv: gfx::handle::RenderTargetView<R, ColourFormat>,
+        encoder: &mut gfx::Encoder<R, C>,
+    ) where
+        C: gfx::CommandBuffer<R>,
+    {
+        self.bundle.data.in_colour.0 = colour_srv;
+        self.bundle.data.out_colour = rtv;
+        self.update_properties_static(srv_size, encoder);
+    }
+
+    fn update<C>(
+        &self,
+        camera_position: Vector2<f32>,
+        zoom: f32,
+        encoder: &mut gfx::Encoder<R, C>,
+    ) where
+        C: gfx::CommandBuffer<R>,
+    {
+        let properties = OutputProperties {
+            camera_position_in_pixels: camera_position.into(),
+            zoom,
+        };
+        encoder.update_constant_buffer(&self.bundle.data.properties, &properties);
+    }
+    fn encode<C>(&self, encoder: &mut gfx::Encoder<R, C>)
+    where
+        C: gfx::CommandBuffer<R>,
+    {
+        self.bundle.encode(encoder);
+    }
+}
+
+gfx_defines! {
+    #[repr(C)]
+    constant LightingPropertiesStatic {
+        region_size_in_pixels: [f32; 2] = "u_RegionSizeInPixels",
+    }
+}
+
+gfx_defines! {
+    #[repr(C)]
+    constant LightingProperties {
+        eye_position_in_pixels: [f32; 2] = "u_EyePositionInPixels",
+        eye_facing: [f32; 2] = "u_EyeFacing",
+        ambient_light: [f32; 3] = "u_AmbientLight",
+        falloff_exponent: f32 = "u_FalloffExponent",
+        num_lights: u32 = "u_NumLights",
+        light_source_radius_in_pixels: f32 = "u_LightSourceRadiusInPixels",
+        num_shadow_samples: u32 = "u_NumShadowSamples",
+        vision_cone_enabled: u32 = "u_VisionConeEnabled",
+        vision_cone_half_angle: f32 = "u_VisionConeHalfAngle",
+        vision_cone_soft_edge_angle: f32 = "u_VisionConeSoftEdgeAngle",
+        vision_cone_range_in_pixels: f32 = "u_VisionConeRangeInPixels",
+        vision_cone_soft_edge_in_pixels: f32 = "u_VisionConeSoftEdgeInPixels",
+        region_position_in_pixels: [f32; 2] = "u_RegionPositionInPixels",
+        explored_size_in_pixels: [f32; 2] = "u_ExploredSizeInPixels",
+        remembered_brightness: f32 = "u_RememberedBrightness",
+        remembered_saturation: f32 = "u_RememberedSaturation",
+    }
+}
+
+/// Controls how lights illuminate the world.
+#[derive(Debug, Clone, Copy)]
+pub struct LightingSettings {
+    /// Light added to everything in line of sight, whether or not any light reaches it
+    pub ambient_light: [f32; 3],
+    /// A light's brightness falls from full at its centre to nothing at its radius, in
+    /// proportion to this power of the remaining fraction of the radius. At 0, lights
+    /// don't fade at all.
+    pub falloff_exponent: f32,
+    /// Lights are treated as discs this size when casting shadows, giving shadows soft
+    /// edges which widen further from whatever casts them.
+    pub light_source_radius_in_pixels: f32,
+    /// Rays traced from each pixel to each light to estimate how much of the light's disc
+    /// is visible. At 1, shadows have hard edges.
+    pub num_shadow_samples: u32,
+    /// If set, the player only sees what's in front of them
+    pub vision_cone: Option<VisionCone>,
+    /// Parts of the map which have been seen before but aren't in view are drawn unlit,
+    /// without entities, at this fraction of their full brightness
+    pub remembered_brightness: f32,
+    /// Between 0, for grey, and 1, for their original colours
+    pub remembered_saturation: f32,
+}
+
+type ExploredFormat = gfx::format::Rgba8;
+type ExploredSurface = <ExploredFormat as gfx::format::Formatted>::Surface;
+type ExploredView = <ExploredFormat as gfx::format::Formatted>::View;
+
+fn create_explored_texture<R, F>(
+    width: u16,
+    height: u16,
+    factory: &mut F,
+) -> (
+    gfx::handle::Texture<R, ExploredSurface>,
+    gfx::handle::ShaderResourceView<R, ExploredView>,
+)
+where
+    R: gfx::Resources,
+    F: gfx::Factory<R>,
+{
+    let tex_kind = gfx::texture::Kind::D2(width, height, gfx::texture::AaMode::Single);
+    let cty = <<ExploredFormat as gfx::format::Formatted>::Channel as gfx::format::ChannelTyped>::get_channel_type();
+    let texture = factory
+        .create_texture::<ExploredSurface>(
+            tex_kind,
+            1,
+            gfx::memory::Bind::SHADER_RESOURCE,
+            gfx::memory::Usage::Dynamic,
+            Some(cty),
+        )
+        .expect("Failed to create texture");
+    let srv = factory
+        .view_texture_as_shader_resource::<ExploredFormat>(
+            &texture,
+            (0, 0),
+            gfx::format::Swizzle::new(),
+        )
+        .expect("Failed to create shader resource view");
+    (texture, srv)
+}
+
+gfx_defines! {
+    #[repr(C)]
+    constant LightProperties {
+        position_in_pixels: [f32; 2] = "position_in_pixels",
+        radius_in_pixels: f32 = "radius_in_pixels",
+        intensity: f32 = "intensity",
+        colour: [f32; 3] = "colour",
+        padding: f32 = "padding",
+    }
+}
+
+gfx_pipeline!(lighting_pipe {
+    quad_corners: gfx::VertexBuffer<QuadCorners> = (),
+    properties: gfx::ConstantBuffer<LightingProperties> = "Properties",
+    properties_static: gfx::ConstantBuffer<LightingPropertiesStatic> = "PropertiesStatic",
+    lights: gfx::ConstantBuffer<LightProperties> = "Lights",
+    in_colour: gfx::TextureSampler<View> = "t_Colour",
+    in_visibility: gfx::TextureSampler<View> = "t_Visibility",
+    in_background: gfx::TextureSampler<View> = "t_Background",
+    in_explored: gfx::TextureSampler<ExploredView> = "t_Explored",
+    out_colour: gfx::BlendTarget<ColourFormat> =
+        ("Target0", gfx::state::ColorMask::all(), gfx::preset::blend::ALPHA),
+    scissor: gfx::Scissor = (),
+});
+
+struct LightingRenderer<R: gfx::Resources> {
+    bundle: gfx::Bundle<R, lighting_pipe::Data<R>>,
+    visibility_srv: gfx::handle::ShaderResourceView<R, View>,
+    settings: LightingSettings,
+    explored_texture: gfx::handle::Texture<R, ExploredSurface>,
+    explored_size: Vector2<f32>,
+}
+
+impl<R: gfx::Resources> LightingRenderer<R> {
+    pub fn new<F, C>(
+        colour_srv: gfx::handle::ShaderResourceView<R, View>,
+        visibility_srv: gfx::handle::ShaderResourceView<R, View>,
+        background_srv: gfx::handle::ShaderResourceView<R, View>,
+        settings: LightingSettings,
+        rtv: gfx::handle::RenderTargetView<R, ColourFormat>,
+        factory: &mut F,
+        encoder: &mut gfx::Encoder<R, C>,
+    ) -> Self
+    where
+        F: gfx::Factory<R> + gfx::traits::FactoryExt<R>,
+        C: gfx::CommandBuffer<R>,
+    {
+        let sampler = factory.create_sampler(gfx::texture::SamplerInfo::new(
+            gfx::texture::FilterMethod::Mipmap,
+            gfx::texture::WrapMode::Tile,
+        ));
+        let explored_sampler = factory.create_sampler(gfx::texture::SamplerInfo::new(
+            gfx::texture::FilterMethod::Bilinear,
+            gfx::texture::WrapMode::Clamp,
+        ));
+        let (explored_texture, explored_srv) = create_explored_texture(1, 1, factory);
+
+        let pso = factory
+            .create_pipeline_simple(
+                include_bytes!("shaders/lighting/shader.150.vert"),
+                include_bytes!("shaders/lighting/shader.150.frag"),
+                lighting_pipe::new(),
+            )
+            .expect("Failed to create pipeline");
+
+        let quad_corners_data = QUAD_COORDS
+            .iter()
+            .map(|v| QuadCorners {
+                corner_zero_to_one: *v,
+            })
+            .collect::<Vec<_>>();
+
+        let (quad_corners_buf, slice) = factory
+            .create_vertex_buffer_with_slice(&quad_corners_data, &QUAD_INDICES[..]);
+
+        let data = lighting_pipe::Data {
+            quad_corners: quad_corners_buf,
+            properties: factory.create_constant_buffer(1),
+            properties_static: factory.create_constant_buffer(1),
+            lights: factory.create_constant_buffer(MAX_NUM_LIGHTS),
+            in_colour: (colour_srv, sampler.clone()),
+            in_visibility: (visibility_srv.clone(), sampler.clone()),
+            in_background: (background_srv, sampler.clone()),
+            in_explored: (explored_srv, explored_sampler),
+            scissor: {
+                let (width, height, _, _) = rtv.get_dimensions();
+                gfx::Rect {
+                    x: 0,
+                    y: 0,
+                    w: width,
+                    h: height,
+                }
+            },
+            out_colour: rtv,
+        };
+        let bundle = gfx::pso::bundle::Bundle::new(slice, pso, data);
+        let lighting_renderer = Self {
+            bundle,
+            visibility_srv,
+            settings,
+            explored_texture,
+            // Nothing has been explored until the first call to `set_explored`
+            explored_size: vec2(1., 1.),
+        };
+        lighting_renderer.update(vec2(0., 0.), vec2(1., 0.), vec2(0., 0.), &[], encoder);
+        lighting_renderer.update_properties_static(encoder);
+        lighting_renderer
+    }
+
+    fn update_properties_static<C>(&self, encoder: &mut gfx::Encoder<R, C>)
+    where
+        C: gfx::CommandBuffer<R>,
+    {
+        let (region_width, region_height, _, _) =
+            self.bundle.data.out_colour.get_dimensions();
+        let properties_static = LightingPropertiesStatic {
+            region_size_in_pixels: [region_width as f32, region_height as f32],
+        };
+        encoder.update_constant_buffer(
+            &self.bundle.data.properties_static,
+            &properties_static,
+        );
+    }
+
+    fn set_targets<C>(
+        &mut self,
+        colour_srv: gfx::handle::ShaderResourceView<R, View>,
+        visibility_srv: gfx::handle::ShaderResourceView<R, View>,
+        background_srv: gfx::handle::ShaderResourceView<R, View>,
+        rtv: gfx::handle::RenderTargetView<R, ColourFormat>,
+        encoder: &mut gfx::Encoder<R, C>,
+    ) where
+        C: gfx::CommandBuffer<R>,
+    {
+        self.bundle.data.in_colour.0 = colour_srv;
+        self.bundle.data.in_visibility.0 = visibility_srv.clone();
+        self.bundle.data.in_background.0 = background_srv;
+        self.bundle.data.out_colour = rtv;
+        self.visibility_srv = visibility_srv;
+        self.update_properties_static(encoder);
+    }
+
+    fn set_explored<F, C>(
+        &mut self,
+        explored_map: &ExploredMap,
+        factory: &mut F,
+        encoder: &mut gfx::Encoder<R, C>,
+    ) where
+        F: gfx::Factory<R>,
+        C: gfx::CommandBuffer<R>,
+    {
+        let (width, height) = (explored_map.width() as u16, explored_map.height() as u16);
+        let (texture_width, texture_height, _, _) =
+            self.explored_texture.get_info().kind.get_dimensions();
+        if (width, height) != (texture_width, texture_height) {
+            let (texture, srv) = create_explored_texture(width, height, factory);
+            self.explored_texture = texture;
+            self.bundle.data.in_explored.0 = srv;
+        }
+        self.explored_size = explored_map.size_in_pixels();
+        let data = explored_map
+            .cells()
+            .iter()
+            .map(|&explored| [explored, explored, explored, 255])
+            .collect::<Vec<_>>();
+        encoder
+            .update_texture::<ExploredSurface, ExploredFormat>(
+                &self.explored_texture,
+                None,
+                self.explored_texture.get_info().to_image_info(0),
+                &data,
+            )
+            .expect("Failed to update texture");
+    }
+
+    /// Positions of the eye and lights are relative to the region's top-left corner, which
+    /// is at `region_position` in the world. Only the first `MAX_NUM_LIGHTS` lights are
+    /// used.
+    fn update<C>(
+        &self,
+        eye_position: Vector2<f32>,
+        eye_facing: Vector2<f32>,
+        region_position: Vector2<f32>,
+        lights: &[LightProperties],
+        encoder: &mut gfx::Encoder<R, C>,
+    ) where
+        C: gfx::CommandBuffer<R>,
+    {
+        let lights = &lights[..lights.len().min(MAX_NUM_LIGHTS)];
+        // The shader's smoothstep is undefined for zero-width edges
+        const MIN_SOFT_EDGE: f32 = 0.001;
+        let vision_cone = self.settings.vision_cone;
+        let properties = LightingProperties {
+            eye_position_in_pixels: eye_position.into(),
+            eye_facing: eye_facing.into(),
+            ambient_light: self.settings.ambient_light,
+            falloff_exponent: self.settings.falloff_exponent,
+            num_lights: lights.len() as u32,
+            light_source_radius_in_pixels: self.settings.light_source_radius_in_pixels,
+            num_shadow_samples: self.settings.num_shadow_samples.max(1),
+            vision_cone_enabled: vision_cone.is_some() as u32,
+            vision_cone_half_angle: vision_cone.map_or(0., |cone| cone.half_angle),
+            vision_cone_soft_edge_angle: vision_cone
+                .map_or(0., |cone| cone.soft_edge_angle)
+                .max(MIN_SOFT_EDGE),
+            vision_cone_range_in_pixels: vision_cone
+                .map_or(0., |cone| cone.range_in_pixels),
+            vision_cone_soft_edge_in_pixels: vision_cone
+                .map_or(0., |cone| cone.soft_edge_in_pixels)
+                .max(MIN_SOFT_EDGE),
+            region_position_in_pixels: region_position.into(),
+            explored_size_in_pixels: self.explored_size.into(),
+            remembered_brightness: self.settings.remembered_brightness,
+            remembered_saturation: self.settings.remembered_saturation,
+        };
+        encoder.update_constant_buffer(&self.bundle.data.properties, &properties);
+        encoder
+            .update_buffer(&self.bundle.data.lights, lights, 0)
+            .expect("Failed to update buffer");
+    }
+
+    fn generate_mipmap<C>(&self, encoder: &mut gfx::Encoder<R, C>)
+    where
+        C: gfx::CommandBuffer<R>,
+    {
+        encoder.generate_mipmap(&self.visibility_srv);
+    }
+
+    /// Only lights `lit_rect`, in pixels from the region's top-left corner, as the rest is
+    /// out of view. Lighting is by far the most expensive pass, and the region is big
+    /// enough for the minimum zoom and a margin around it.
+    fn encode<C>(&mut self, lit_rect: gfx::Rect, encoder: &mut gfx::Encoder<R, C>)
+    where
+        C: gfx::CommandBuffer<R>,
+    {
+        // The lighting pass flips the region, so its top-left corner is at the origin of
+        // the target
+        self.bundle.data.scissor = lit_rect;
+        self.bundle.encode(encoder);
+    }
+}
+
+gfx_defines! {
+    #[repr(C)]
+    constant MapProperties {
+        output_size_in_pixels: [f32; 2] = "u_OutputSizeInPixels",
+        tile_position_in_pixels: [f32; 2] = "u_TilePositionInPixels",
+        tile_size_in_pixels: [f32; 2] = "u_TileSizeInPixels",
+    }
+}
+
+gfx_pipeline!(map_pipe {
+    quad_corners: gfx::VertexBuffer<QuadCorners> = (),
+    properties: gfx::ConstantBuffer<MapProperties> = "Properties",
+    image: gfx::TextureSampler<View> = "t_Image",
+    out_background: gfx::RenderTarget<ColourFormat> = "TargetBackground",
+    out_visibility: gfx::BlendTarget<ColourFormat> =
+        ("TargetVisibility", gfx::state::ColorMask::all(), gfx::preset::blend::ALPHA),
+    out_colour: gfx::BlendTarget<ColourFormat> =
+        ("TargetColour", gfx::state::ColorMask::all(), gfx::preset::blend::ALPHA),
+});
+
+fn create_map_texture<R, F>(
+    image: &image::RgbaImage,
+    factory: &mut F,
+) -> gfx::handle::ShaderResourceView<R, View>
+where
+    R: gfx::Resources,
+    F: gfx::Factory<R>,
+{
+    let (image_width, image_height) = image.dimensions();
+    let tex_kind = gfx::texture::Kind::D2(
+        image_width as u16,
+        image_height as u16,
+        gfx::texture::AaMode::Single,
+    );
+    let tex_mipmap = gfx::texture::Mipmap::Allocated;
+    let (_, texture_srv) = factory
+        .create_texture_immutable_u8::<ColourFormat>(tex_kind, tex_mipmap, &[image])
+        .expect("failed to create texture");
+    texture_srv
+}
+
+struct MapTile<R: gfx::Resources> {
+    position: Vector2<f32>,
+    size: Vector2<f32>,
+    texture_srv: gfx::handle::ShaderResourceView<R, View>,
+}
+
+fn create_map_tiles<R, F>(image: &image::RgbaImage, factory: &mut F) -> Vec<MapTile<R>>
+where
+    R: gfx::Resources,
+    F: gfx::Factory<R>,
+{
+    let (image_width, image_height) = image.dimensions();
+    let mut tiles = Vec::new();
+    for tile_y in (0..image_height).step_by(MAP_TILE_SIZE as usize) {
+        for tile_x in (0..image_width).step_by(MAP_TILE_SIZE as usize) {
+            let width = MAP_TILE_SIZE.min(image_width - tile_x);
+            let height = MAP_TILE_SIZE.min(image_height - tile_y);
+            let tile_image = image::RgbaImage::from_fn(width, height, |x, y| {
+                *image.get_pixel(tile_x + x, tile_y + y)
+            });
+            tiles.push(MapTile {
+                position: vec2(tile_x as f32, tile_y as f32),
+                size: vec2(width as f32, height as f32),
+                texture_srv: create_map_texture(&tile_image, factory),
+            });
+        }
+    }
+    tiles
+}
+
+struct MapRenderer<R: gfx::Resources> {
+    bundle: gfx::Bundle<R, map_pipe::Data<R>>,
+    tiles: Vec<MapTile<R>>,
+}
+
+impl<R: gfx::Resources> MapRenderer<R> {
+    pub fn new<F>(
+        image: &image::RgbaImage,
+        colour_rtv: gfx::handle::RenderTargetView<R, ColourFormat>,
+        visibility_rtv: gfx::handle::RenderTargetView<R, ColourFormat>,
+        background_rtv: gfx::handle::RenderTargetView<R, ColourFormat>,
+        factory: &mut F,
+    ) -> Self
+    where
+        F: gfx::Factory<R> + gfx::traits::FactoryExt<R>,
+    {
+        let tiles = create_map_tiles(image, factory);
+        let sampler_info = gfx::texture::SamplerInfo {
+            filter: gfx::texture::FilterMethod::Trilinear,
+            wrap_mode: (
+                gfx::texture::WrapMode::Tile,
+                gfx::texture::WrapMode::Tile,
+                gfx::texture::WrapMode::Tile,
+            ),
+            lod_bias: gfx::texture::Lod::from(0.),
+            lod_range: (gfx::texture::Lod::from(0.), gfx::texture::Lod::from(100.)),
+            comparison: Some(gfx::state::Comparison::Equal),
+            border: gfx::texture::PackedColor(0),
+        };
+        let sampler = factory.create_sampler(sampler_info);
+
+        let pso = factory
+            .create_pipeline_simple(
+                include_bytes!("shaders/map/shader.150.vert"),
+                include_bytes!("shaders/map/shader.150.frag"),
+                map_pipe::new(),
+            )
+            .expect("Failed to create pipeline");
+
+        let quad_corners_data = QUAD_COORDS
+            .iter()
+            .map(|v| QuadCorners {
+                corner_zero_to_one: *v,
+            })
+            .collect::<Vec<_>>();
+
+        let (quad_corners_buf, slice) = factory
+            .create_vertex_buffer_with_slice(&quad_corners_data, &QUAD_INDICES[..]);
+
+        let data = map_pipe::Data {
+            quad_corners: quad_corners_buf,
+            properties: factory.create_constant_buffer(1),
+            image: (tiles[0].texture_srv.clone(), sampler),
+            out_background: background_rtv,
+            out_visibility: visibility_rtv,
+            out_colour: colour_rtv,
+        };
+        let bundle = gfx::pso::bundle::Bundle::new(slice, pso, data);
+        Self { bundle, tiles }
+    }
+
+    fn set_targets(
+        &mut self,
+        colour_rtv: gfx::handle::RenderTargetView<R, ColourFormat>,
+        visibility_rtv: gfx::handle::RenderTargetView<R, ColourFormat>,
+        background_rtv: gfx::handle::RenderTargetView<R, ColourFormat>,
+    ) {
+        self.bundle.data.out_colour = colour_rtv;
+        self.bundle.data.out_visibility = visibility_rtv;
+        self.bundle.data.out_background = background_rtv;
+    }
+
+    fn set_image<F>(&mut self, image: &image::RgbaImage, factory: &mut F)
+    where
+        F: gfx::Factory<R>,
+    {
+        self.tiles = create_map_tiles(image, factory);
+    }
+
+    /// Draws the tiles overlapping the region of the world whose top-left corner is at
+    /// `region_origin`. Parts of the region outside the map are opaque.
+    fn encode<C>(&mut self, region_origin: Vector2<f32>, encoder: &mut gfx::Encoder<R, C>)
+    where
+        C: gfx::CommandBuffer<R>,
+    {
+        encoder.clear(&self.bundle.data.out_colour, [0., 0., 0., 1.]);
+        encoder.clear(&self.bundle.data.out_visibility, [0., 0., 0., 1.]);
+        encoder.clear(&self.bundle.data.out_background, [0., 0., 0., 1.]);
+        let (region_width, region_height, _, _) =
+            self.bundle.data.out_colour.get_dimensions();
+        let region_size = vec2(region_width as f32, region_height as f32);
+        for tile in self.tiles.iter() {
+            let position = tile.position - region_origin;
+            if position.x >= region_size.x
+                || position.y >= region_size.y
+                || position.x + tile.size.x <= 0.
+                || position.y + tile.size.y <= 0.
+            {
+                continue;
+            }
+            let properties = MapProperties {
+                output_size_in_pixels: region_size.into(),
+                tile_position_in_pixels: position.into(),
+                tile_size_in_pixels: tile.size.into(),
+            };
+            encoder.update_constant_buffer(&self.bundle.data.properties, &properties);
+            self.bundle.data.image.0 = tile.texture_srv.clone();
+            self.bundle.encode(encoder);
+        }
+    }
+}
+
+gfx_vertex_struct!(QuadCorners {
+    corner_zero_to_one: [f32; 2] = "a_CornerZeroToOne",
+});
+
+gfx_vertex_struct!(QuadInstance {
+    position_of_centre_in_pixels: [f32; 2] = "i_PositionOfCentreInPixels",
+    dimensions_in_pixels: [f32; 2] = "i_DimensionsInPixels",
+    facing_vector: [f32; 2] = "i_FacingVector",
+    sprite_position_of_top_left_in_pixels: [f32; 2] = "i_SpritePositionOfTopLeftInPixels",
+    sprite_dimensions_in_pixels: [f32; 2] = "i_SpriteDimensionsInPixels",
+    occludes: f32 = "i_Occludes",
+});
+
+gfx_defines! {
+    #[repr(C)]
+    constant QuadProperties {
+        region_position_of_top_left_in_pixels: [f32; 2] = "u_RegionPositionOfTopLeftInPixels",
+        region_size_in_pixels: [f32; 2] = "u_RegionSizeInPixels",
+        sprite_sheet_size_in_pixels: [f32; 2] = "u_SpriteSheetSizeInPixels",
+        sprite_scale: f32 = "u_SpriteScale",
+        flip_vertically: u32 = "u_FlipVertically",
+    }
+}
+
+gfx_pipeline!(quad_pipe {
+    quad_corners: gfx::VertexBuffer<QuadCorners> = (),
+    quad_instances: gfx::InstanceBuffer<QuadInstance> = (),
+    properties: gfx::ConstantBuffer<QuadProperties> = "Properties",
+    sprite_sheet: gfx::TextureSampler<View> = "t_SpriteSheet",
+    out_visibility: gfx::BlendTarget<ColourFormat> =
+        ("TargetVisibility", gfx::state::ColorMask::all(), gfx::preset::blend::ALPHA),
+    out_colour: gfx::BlendTarget<ColourFormat> =
+        ("TargetColour", gfx::state::ColorMask::all(), gfx::preset::blend::ALPHA),
+});
+
+gfx_pipeline!(overlay_pipe {
+    quad_corners: gfx::VertexBuffer<QuadCorners> = (),
+    quad_instances: gfx::InstanceBuffer<QuadInstance> = (),
+    properties: gfx::ConstantBuffer<QuadProperties> = "Properties",
+    sprite_sheet: gfx::TextureSampler<View> = "t_SpriteSheet",
+    out_colour: gfx::BlendTarget<ColourFormat> =
+        ("TargetColour", gfx::state::ColorMask::all(), gfx::preset::blend::ALPHA),
+});
+
+/// The instances of one render layer, written each frame and copied to the layer's
+/// instance buffer when it's drawn. Both buffers grow as needed to hold every instance.
+struct QuadBatch<R: gfx::Resources> {
+    name: &'static str,
+    upload: gfx::handle::Buffer<R, QuadInstance>,
+    capacity: usize,
+    num_quads: usize,
+}
+
+impl<R: gfx::Resources> QuadBatch<R> {
+    /// Creates the batch, and the instance buffer for it to be drawn from. `name` is used to
+    /// identify the batch in warnings.
+    fn new<F>(
+        name: &'static str,
+        factory: &mut F,
+    ) -> (Self, gfx::handle::Buffer<R, QuadInstance>)
+    where
+        F: gfx::Factory<R> + gfx::traits::FactoryExt<R>,
+    {
+        let batch = Self {
+            name,
+            upload: factory
+                .create_upload_buffer(INITIAL_NUM_QUADS)
+                .expect("Failed to create instance upload buffer"),
+            capacity: INITIAL_NUM_QUADS,
+            num_quads: 0,
+        };
+        let instances = create_instance_buffer(INITIAL_NUM_QUADS, factory)
+            .expect("Failed to create instance buffer");
+        (batch, instances)
+    }
+
+    /// Writes `instances` to the upload buffer, first replacing it and the instance buffer
+    /// the batch is drawn from with larger ones if they're too small.
+    fn write<F>(
+        &mut self,
+        instances: &[QuadInstance],
+        instance_buffer: &mut gfx::handle::Buffer<R, QuadInstance>,
+        factory: &mut F,
+    ) where
+        F: gfx::Factory<R> + gfx::traits::FactoryExt<R>,
+    {
+        if instances.len() > self.capacity {
+            let capacity = instances.len().next_power_of_two();
+            eprintln!(
+                "Warning: growing {} instance buffers from {} to {} quads",
+                self.name, self.capacity, capacity
+            );
+            self.upload = factory
+                .create_upload_buffer(capacity)
+                .expect("Failed to create instance upload buffer");
+            *instance_buffer = create_instance_buffer(capacity, factory)
+                .expect("Failed to create instance buffer");
+            self.capacity = capacity;
+        }
+        let mut writer = factory
+            .write_mapping(&self.upload)
+            .expect("Failed to map upload buffer");
+        writer[..instances.len()].copy_from_slice(instances);
+        self.num_quads = instances.len();
+    }
+
+    fn copy_to<C>(
+        &self,
+        instances: &gfx::handle::Buffer<R, QuadInstance>,
+        encoder: &mut gfx::Encoder<R, C>,
+    ) where
+        C: gfx::CommandBuffer<R>,
+    {
+        encoder
+            .copy_buffer(&self.upload, instances, 0, 0, self.num_quads)
+            .expect("Failed to copy instances");
+    }
+}
+
+/// Draws entities' sprites in each `RenderLayer`. The world layer is drawn into the region's
+/// colour and visibility targets before lighting, the overlay layer into the lit output
+/// and the UI layer straight into the window.
+struct QuadRenderer<R: gfx::Resources> {
+    world_bundle: gfx::Bundle<R, quad_pipe::Data<R>>,
+    overlay_bundle: gfx::Bundle<R, overlay_pipe::Data<R>>,
+    ui_bundle: gfx::Bundle<R, overlay_pipe::Data<R>>,
+    world_batch: QuadBatch<R>,
+    overlay_batch: QuadBatch<R>,
+    ui_batch: QuadBatch<R>,
+    sprite_sheet_size_in_pixels: [f32; 2],
+    sprite_scale: f32,
+}
+
+impl<R: gfx::Resources> QuadRenderer<R> {
+    pub fn new<F, C>(
+        sprite_atlas: &SpriteAtlas,
+        colour_rtv: gfx::handle::RenderTargetView<R, ColourFormat>,
+        visibility_rtv: gfx::handle::RenderTargetView<R, ColourFormat>,
+        output_rtv: gfx::handle::RenderTargetView<R, ColourFormat>,
+        window_rtv: gfx::handle::RenderTargetView<R, ColourFormat>,
+        factory: &mut F,
+        encoder: &mut gfx::Encoder<R, C>,
+    ) -> Self
+    where
+        F: gfx::Factory<R> + gfx::traits::FactoryExt<R>,
+        C: gfx::CommandBuffer<R>,
+    {
+        let sprite_sheet_max = sprite_atlas.size_in_pixels();
+        let sprite_scale = 1;
+
+        let mut image = image::load_from_memory(include_bytes!("images/sprites.png"))
+            .expect("Failed to decode image");
+        let image = image.crop(0, 0, sprite_sheet_max[0], sprite_sheet_max[1]);
+        let image = image
+            .resize(
+                sprite_scale * image.width(),
+                sprite_scale * image.height(),
+                image::FilterType::Nearest,
+            )
+            .to_rgba();
+        let (image_width, image_height) = image.dimensions();
+        let tex_kind = gfx::texture::Kind::D2(
+            image_width as u16,
+            image_height as u16,
+            gfx::texture::AaMode::Single,
+        );
+        let tex_mipmap = gfx::texture::Mipmap::Allocated;
+        let (_, texture_srv) = factory
+            .create_texture_immutable_u8::<ColourFormat>(tex_kind, tex_mipmap, &[&image])
+            .expect("failed to create texture");
+        let mut info = gfx::texture::SamplerInfo::new(
+            gfx::texture::FilterMethod::Scale,
+            gfx::texture::WrapMode::Border,
+        );
+        info.border = gfx::texture::PackedColor(0);
+        let sampler = factory.create_sampler(info);
+
+        encoder.generate_mipmap(&texture_srv);
+
+        let world_pso = factory
+            .create_pipeline_simple(
+                include_bytes!("shaders/quad/shader.150.vert"),
+                include_bytes!("shaders/quad/shader.150.frag"),
+                quad_pipe::new(),
+            )
+            .expect("Failed to create pipeline");
+
+        let overlay_pso = factory
+            .create_pipeline_simple(
+                include_bytes!("shaders/quad/shader.150.vert"),
+                include_bytes!("shaders/overlay/shader.150.frag"),
+                overlay_pipe::new(),
+            )
+            .expect("Failed to create pipeline");
+
+        let quad_corners_data = QUAD_COORDS
+            .iter()
+            .map(|v| QuadCorners {
+                corner_zero_to_one: *v,
+            })
+            .collect::<Vec<_>>();
+
+        let (quad_corners_buf, slice) = factory
+            .create_vertex_buffer_with_slice(&quad_corners_data, &QUAD_INDICES[..]);
+
+        let (world_batch, world_instances) = QuadBatch::new("world", factory);
+        let (overlay_batch, overlay_instances) = QuadBatch::new("overlay", factory);
+        let (ui_batch, ui_instances) = QuadBatch::new("UI", factory);
+
+        let mut overlay_data = |quad_instances, out_colour| overlay_pipe::Data {
+            quad_corners: quad_corners_buf.clone(),
+            quad_instances,
+            properties: factory.create_constant_buffer(1),
+            sprite_sheet: (texture_srv.clone(), sampler.clone()),
+            out_colour,
+        };
+        let overlay_bundle = gfx::pso::bundle::Bundle::new(
+            slice.clone(),
+            overlay_pso.clone(),
+            overlay_data(overlay_instances, output_rtv),
+        );
+        let ui_bundle = gfx::pso::bundle::Bundle::new(
+            slice.clone(),
+            overlay_pso,
+            overlay_data(ui_instances, window_rtv),
+        );
+
+        let world_data = quad_pipe::Data {
+            quad_corners: quad_corners_buf,
+            quad_instances: world_instances,
+            properties: factory.create_constant_buffer(1),
+            sprite_sheet: (texture_srv, sampler),
+            out_colour: colour_rtv,
+            out_visibility: visibility_rtv,
+        };
+        let world_bundle = gfx::pso::bundle::Bundle::new(slice, world_pso, world_data);
+
+        Self {
+            world_bundle,
+            overlay_bundle,
+            ui_bundle,
+            world_batch,
+            overlay_batch,
+            ui_batch,
+            sprite_sheet_size_in_pixels: [image_width as f32, image_height as f32],
+            sprite_scale: sprite_scale as f32,
+        }
+    }
+
+    fn set_targets(
+        &mut self,
+        colour_rtv: gfx::handle::RenderTargetView<R, ColourFormat>,
+        visibility_rtv: gfx::handle::RenderTargetView<R, ColourFormat>,
+        output_rtv: gfx::handle::RenderTargetView<R, ColourFormat>,
+        window_rtv: gfx::handle::RenderTargetView<R, ColourFormat>,
+    ) {
+        self.world_bundle.data.out_colour = colour_rtv;
+        self.world_bundle.data.out_visibility = visibility_rtv;
+        self.overlay_bundle.data.out_colour = output_rtv;
+        self.ui_bundle.data.out_colour = window_rtv;
+    }
+
+    fn properties(
+        &self,
+        region_position_of_top_left_in_pixels: Vector2<f32>,
+        region_size_in_pixels: Vector2<f32>,
+        flip_vertically: bool,
+    ) -> QuadProperties {
+        QuadProperties {
+            region_position_of_top_left_in_pixels: region_position_of_top_left_in_pixels
+                .into(),
+            region_size_in_pixels: region_size_in_pixels.into(),
+            sprite_sheet_size_in_pixels: self.sprite_sheet_size_in_pixels,
+            sprite_scale: self.sprite_scale,
+            flip_vertically: flip_vertically as u32,
+        }
+    }
+
+    fn update<'a, F, C, I>(
+        &mut self,
+        to_render: I,
+        region_origin: Vector2<f32>,
+        factory: &mut F,
+        encoder: &mut gfx::Encoder<R, C>,
+    ) where
+        F: gfx::Factory<R> + gfx::traits::FactoryExt<R>,
+        C: gfx::CommandBuffer<R>,
+        I: IntoIterator<Item = ToRender<'a>>,
+    {
+        // The lighting pass flips the region vertically, so the overlay must be flipped to
+        // match, and the window is the same way up as the lighting output.
+        let (region_width, region_height, _, _) =
+            self.world_bundle.data.out_colour.get_dimensions();
+        let region_size = vec2(region_width as f32, region_height as f32);
+        let world_properties = self.properties(region_origin, region_size, false);
+        let overlay_properties = self.properties(region_origin, region_size, true);
+        let (window_width, window_height, _, _) =
+            self.ui_bundle.data.out_colour.get_dimensions();
+        // UI quads are positioned in window pixels from its top-left corner, whatever the
+        // camera is looking at
+        let ui_properties = self.properties(
+            vec2(0., 0.),
+            vec2(window_width as f32, window_height as f32),
+            true,
+        );
+        encoder.update_constant_buffer(
+            &self.world_bundle.data.properties,
+            &world_properties,
+        );
+        encoder.update_constant_buffer(
+            &self.overlay_bundle.data.properties,
+            &overlay_properties,
+        );
+        encoder.update_constant_buffer(&self.ui_bundle.data.properties, &ui_properties);
+
+        let mut world = Vec::new();
+        let mut overlay = Vec::new();
+        let mut ui = Vec::new();
+        for to_render in to_render {
+            let instance = QuadInstance {
+                position_of_centre_in_pixels: to_render.physics.centre_position.into(),
+                dimensions_in_pixels: to_render.physics.bounding_dimensions.into(),
+                facing_vector: to_render.physics.facing.into(),
+                sprite_position_of_top_left_in_pixels: to_render
+                    .graphics
+                    .sprite
+                    .position_of_top_left_in_pixels,
+                sprite_dimensions_in_pixels: to_render
+                    .graphics
+                    .sprite
+                    .dimensions_in_pixels,
+                occludes: (to_render.graphics.occludes as u8) as f32,
+            };
+            match to_render.graphics.layer {
+                RenderLayer::World => world.push(instance),
+                RenderLayer::Overlay => overlay.push(instance),
+                RenderLayer::Ui => ui.push(instance),
+            }
+        }
+        self.world_batch.write(
+            &world,
+            &mut self.world_bundle.data.quad_instances,
+            factory,
+        );
+        self.overlay_batch.write(
+            &overlay,
+            &mut self.overlay_bundle.data.quad_instances,
+            factory,
+        );
+        self.ui_batch
+            .write(&ui, &mut self.ui_bundle.data.quad_instances, factory);
+        self.world_bundle.slice.instances = Some((self.world_batch.num_quads as u32, 0));
+        self.overlay_bundle.slice.instances =
+            Some((self.overlay_batch.num_quads as u32, 0));
+        self.ui_bundle.slice.instances = Some((self.ui_batch.num_quads as u32, 0));
+    }
+
+    /// Draws the world layer. Call after the map has been drawn and before lighting.
+    fn encode_world<C>(&self, encoder: &mut gfx::Encoder<R, C>)
+    where
+        C: gfx::CommandBuffer<R>,
+    {
+        self.world_batch
+            .copy_to(&self.world_bundle.data.quad_instances, encoder);
+        self.world_bundle.encode(encoder);
+    }
+
+    /// Draws the overlay layer. Call after lighting and before the output is scaled into
+    /// the window.
+    fn encode_overlay<C>(&self, encoder: &mut gfx::Encoder<R, C>)
+    where
+        C: gfx::CommandBuffer<R>,
+    {
+        self.overlay_batch
+            .copy_to(&self.overlay_bundle.data.quad_instances, encoder);
+        self.overlay_bundle.encode(encoder);
+    }
+
+    /// Draws the UI layer. Call last.
+    fn encode_ui<C>(&self, encoder: &mut gfx::Encoder<R, C>)
+    where
+        C: gfx::CommandBuffer<R>,
+    {
+        self.ui_batch
+            .copy_to(&self.ui_bundle.data.quad_instances, encoder);
+        self.ui_bundle.encode(encoder);
+    }
+}
+
+fn create_instance_buffer<R, F, T>(
+    size: usize,
+    factory: &mut F,
+) -> Result<gfx::handle::Buffer<R, T>, gfx::buffer::CreationError>
+where
+    R: gfx::Resources,
+    F: gfx::Factory<R> + gfx::traits::FactoryExt<R>,
+{
+    factory.create_buffer(
+        size,
+        gfx::buffer::Role::Vertex,
+        gfx::memory::Usage::Data,
+        gfx::memory::Bind::TRANSFER_DST,
+    )
+}
+
+/// The size of the part of the region needed to contain everything visible across
+/// `window_size` pixels of the window at `zoom`, wherever the aligned region starts.
+fn visible_region_size(window_size: u16, zoom: f32) -> u16 {
+    let visible_size = window_size as f32 / zoom;
+    (((visible_size + REGION_ALIGNMENT) / REGION_ALIGNMENT).ceil() * REGION_ALIGNMENT)
+        as u16
+}
+
+/// Render targets covering the part of the world around the camera, which everything is
+/// drawn into before being scaled into the window.
+struct RegionTargets<R: gfx::Resources> {
+    width: u16,
+    height: u16,
+    colour_srv: gfx::handle::ShaderResourceView<R, View>,
+    colour_rtv: gfx::handle::RenderTargetView<R, ColourFormat>,
+    visibility_srv: gfx::handle::ShaderResourceView<R, View>,
+    visibility_rtv: gfx::handle::RenderTargetView<R, ColourFormat>,
+    /// The map alone, without entities
+    background_srv: gfx::handle::ShaderResourceView<R, View>,
+    background_rtv: gfx::handle::RenderTargetView<R, ColourFormat>,
+    output_srv: gfx::handle::ShaderResourceView<R, View>,
+    output_rtv: gfx::handle::RenderTargetView<R, ColourFormat>,
+}
+
+impl<R: gfx::Resources> RegionTargets<R> {
+    /// Creates targets big enough to contain everything visible in a window of the given
+    /// size at the minimum zoom, wherever the aligned region starts, plus the margin.
+    fn new<F>(window_width: u16, window_height: u16, factory: &mut F) -> Self
+    where
+        F: gfx::Factory<R>,
+    {
+        let region_size = |window_size: u16| {
+            visible_region_size(window_size, MIN_ZOOM) + 2 * REGION_MARGIN as u16
+        };
+        let (width, height) = (region_size(window_width), region_size(window_height));
+
+        let (_, output_srv, output_rtv) = factory
+            .create_render_target(width, height)
+            .expect("Failed to create render target");
+
+        let (_, colour_srv, colour_rtv) = factory
+            .create_render_target(width, height)
+            .expect("Failed to create render target");
+
+        let (_, background_srv, background_rtv) = factory
+            .create_render_target(width, height)
+            .expect("Failed to create render target");
+
+        let tex_kind =
+            gfx::texture::Kind::D2(width, height, gfx::texture::AaMode::Single);
+
+        let cty = <<Format as gfx::format::Formatted>::Channel as gfx::format::ChannelTyped>::get_channel_type();
+
+        let visibility_tex: gfx::handle::Texture<R, Surface> = factory
+            .create_texture::<Surface>(
+                tex_kind,
+                tex_kind.get_num_levels(),
+                gfx::memory::Bind::SHADER_RESOURCE | gfx::memory::Bind::RENDER_TARGET,
+                gfx::memory::Usage::Data,
+                Some(cty),
+            )
+            .expect("Failed to create texture");
+
+        let visibility_srv: gfx::handle::ShaderResourceView<R, View> = factory
+            .view_texture_as_shader_resource::<Format>(
+                &visibility_tex,
+                (0, tex_kind.get_num_levels()),
+                gfx::format::Swizzle::new(),
+            )
+            .unwrap();
+
+        let visibility_rtv: gfx::handle::RenderTargetView<R, Format> = factory
+            .view_texture_as_render_target::<Format>(&visibility_tex, 0, None)
+            .unwrap();
+
+        Self {
+            width,
+            height,
+            colour_srv,
+            colour_rtv,
+            visibility_srv,
+            visibility_rtv,
+            background_srv,
+            background_rtv,
+            output_srv,
+            output_rtv,
+        }
+    }
+
+    fn size(&self) -> Vector2<f32> {
+        vec2(self.width as f32, self.height as f32)
+    }
+}
+
+/// Owns the render targets and renderers needed to draw a `GameState` into a window.
+pub struct Renderer<R: gfx::Resources> {
+    region_targets: RegionTargets<R>,
+    map_renderer: MapRenderer<R>,
+    quad_renderer: QuadRenderer<R>,
+    lighting_renderer: LightingRenderer<R>,
+    output_renderer: OutputRenderer<R>,
+}
+
+impl<R: gfx::Resources> Renderer<R> {
+    pub fn new<F, C>(
+        map_image: &image::RgbaImage,
+        sprite_atlas: &SpriteAtlas,
+        lighting_settings: LightingSettings,
+        rtv: gfx::handle::RenderTargetView<R, ColourFormat>,
+        factory: &mut F,
+        encoder: &mut gfx::Encoder<R, C>,
+    ) -> Self
+    where
+        F: gfx::Factory<R> + gfx::traits::FactoryExt<R>,
+        C: gfx::CommandBuffer<R>,
+    {
+        let (window_width, window_height, _, _) = rtv.get_dimensions();
+        let region_targets = RegionTargets::new(window_width, window_height, factory);
+
+        let quad_renderer = QuadRenderer::new(
+            sprite_atlas,
+            region_targets.colour_rtv.clone(),
+            region_targets.visibility_rtv.clone(),
+            region_targets.output_rtv.clone(),
+            rtv.clone(),
+            factory,
+            encoder,
+        );
+
+        let map_renderer = MapRenderer::new(
+            map_image,
+            region_targets.colour_rtv.clone(),
+            region_targets.visibility_rtv.clone(),
+            region_targets.background_rtv.clone(),
+            factory,
+        );
+
+        let lighting_renderer = LightingRenderer::new(
+            region_targets.colour_srv.clone(),
+            region_targets.visibility_srv.clone(),
+            region_targets.background_srv.clone(),
+            lighting_settings,
+            region_targets.output_rtv.clone(),
+            factory,
+            encoder,
+        );
+
+        let output_renderer = OutputRenderer::new(
+            region_targets.output_srv.clone(),
+            region_targets.size(),
+            rtv,
+            factory,
+            encoder,
+        );
+
+        Self {
+            region_targets,
+            map_renderer,
+            quad_renderer,
+            lighting_renderer,
+            output_renderer,
+        }
+    }
+
+    /// Call after the window has been resized, with the new window render target.
+    pub fn resize<F, C>(
+        &mut self,
+        rtv: gfx::handle::RenderTargetView<R, ColourFormat>,
+        factory: &mut F,
+        encoder: &mut gfx::Encoder<R, C>,
+    ) where
+        F: gfx::Factory<R>,
+        C: gfx::CommandBuffer<R>,
+    {
+        let (window_width, window_height, _, _) = rtv.get_dimensions();
+        self.region_targets = RegionTargets::new(window_width, window_height, factory);
+        let region_targets = &self.region_targets;
+        self.map_renderer.set_targets(
+            region_targets.colour_rtv.clone(),
+            region_targets.visibility_rtv.clone(),
+            region_targets.background_rtv.clone(),
+        );
+        self.quad_renderer.set_targets(
+            region_targets.colour_rtv.clone(),
+            region_targets.visibility_rtv.clone(),
+            region_targets.output_rtv.clone(),
+            rtv.clone(),
+        );
+        self.lighting_renderer.set_targets(
+            region_targets.colour_srv.clone(),
+            region_targets.visibility_srv.clone(),
+            region_targets.background_srv.clone(),
+            region_targets.output_rtv.clone(),
+            encoder,
+        );
+        self.output_renderer.set_targets(
+            region_targets.output_srv.clone(),
+            region_targets.size(),
+            rtv,
+            encoder,
+        );
+    }
+
+    pub fn set_map<F>(&mut self, map_image: &image::RgbaImage, factory: &mut F)
+    where
+        F: gfx::Factory<R>,
+    {
+        self.map_renderer.set_image(map_image, factory);
+    }
+
+    /// Call whenever more of the world has been explored, or the explored map is replaced.
+    pub fn set_explored<F, C>(
+        &mut self,
+        explored_map: &ExploredMap,
+        factory: &mut F,
+        encoder: &mut gfx::Encoder<R, C>,
+    ) where
+        F: gfx::Factory<R>,
+        C: gfx::CommandBuffer<R>,
+    {
+        self.lighting_renderer
+            .set_explored(explored_map, factory, encoder);
+    }
+
+    fn window_size(&self) -> (u16, u16) {
+        let (window_width, window_height, _, _) =
+            self.output_renderer.bundle.data.out_colour.get_dimensions();
+        (window_width, window_height)
+    }
+
+    /// The size of the part of the world visible in the window
+    fn visible_size(&self, camera_view: CameraView) -> Vector2<f32> {
+        let (window_width, window_height) = self.window_size();
+        vec2(window_width as f32, window_height as f32) / camera_view.zoom
+    }
+
+    /// Returns the position in the world of the top-left corner of the region drawn for the
+    /// given view of the world.
+    fn region_origin(&self, camera_view: CameraView) -> Vector2<f32> {
+        let top_left = camera_view.centre - self.visible_size(camera_view) / 2.;
+        vec2(
+            (top_left.x / REGION_ALIGNMENT).floor() * REGION_ALIGNMENT,
+            (top_left.y / REGION_ALIGNMENT).floor() * REGION_ALIGNMENT,
+        ) - vec2(REGION_MARGIN, REGION_MARGIN)
+    }
+
+    /// Returns the lights which reach into view, nearest to the camera first, with
+    /// positions relative to the region. Lights may be outside the region, in which case
+    /// the lighting shader traces their shadows as far as the region's edge.
+    fn lights_in_view<'a, I>(
+        &self,
+        to_light: I,
+        region_origin: Vector2<f32>,
+        camera_view: CameraView,
+    ) -> Vec<LightProperties>
+    where
+        I: Iterator<Item = ToLight<'a>>,
+    {
+        let half_visible_size = self.visible_size(camera_view) / 2.;
+        let (view_min, view_max) = (
+            camera_view.centre - half_visible_size,
+            camera_view.centre + half_visible_size,
+        );
+        let mut lights = to_light
+            .filter(|to_light| {
+                let position = to_light.position;
+                let nearest_in_view = vec2(
+                    position.x.clamp(view_min.x, view_max.x),
+                    position.y.clamp(view_min.y, view_max.y),
+                );
+                (nearest_in_view - position).magnitude2()
+                    < to_light.light.radius * to_light.light.radius
+            })
+            .map(|to_light| (to_light.position - region_origin, to_light.light))
+            .collect::<Vec<_>>();
+        let camera_position = camera_view.centre - region_origin;
+        lights.sort_by(|&(a, _), &(b, _)| {
+            (a - camera_position)
+                .magnitude2()
+                .partial_cmp(&(b - camera_position).magnitude2())
+                .expect("light position is NaN")
+        });
+        lights
+            .into_iter()
+            .map(|(position, light)| LightProperties {
+                position_in_pixels: position.into(),
+                radius_in_pixels: light.radius,
+                intensity: light.intensity,
+                colour: light.colour,
+                padding: 0.,
+            })
+            .collect()
+    }
+
+    pub fn render<F, C>(
+        &mut self,
+        game_state: &GameState,
+        camera_view: CameraView,
+        alpha: f32,
+        factory: &mut F,
+        encoder: &mut gfx::Encoder<R, C>,
+    ) where
+        F: gfx::Factory<R> + gfx::traits::FactoryExt<R>,
+        C: gfx::CommandBuffer<R>,
+    {
+        let player_info = game_state.player_info(alpha);
+        let player_position = player_info.physics.centre_position;
+        let region_origin = self.region_origin(camera_view);
+
+        self.quad_renderer.update(
+            game_state.to_render(alpha),
+            region_origin,
+            factory,
+            encoder,
+        );
+        self.map_renderer.encode(region_origin, encoder);
+        self.quad_renderer.encode_world(encoder);
+
+        self.lighting_renderer.generate_mipmap(encoder);
+
+        let lights =
+            self.lights_in_view(game_state.to_light(alpha), region_origin, camera_view);
+        self.lighting_renderer.update(
+            player_position - region_origin,
+            player_info.physics.facing,
+            region_origin,
+            &lights,
+            encoder,
+        );
+        let (window_width, window_height) = self.window_size();
+        let lit_rect = gfx::Rect {
+            x: REGION_MARGIN as u16,
+            y: REGION_MARGIN as u16,
+            w: visible_region_size(window_width, camera_view.zoom),
+            h: visible_region_size(window_height, camera_view.zoom),
+        };
+        self.lighting_renderer.encode(lit_rect, encoder);
+        self.quad_renderer.encode_overlay(encoder);
+        self.output_renderer.update(
+            camera_view.centre - region_origin,
+            camera_view.zoom,
+            encoder,
+        );
+        self.output_renderer.encode(encoder);
+        self.quad_renderer.encode_ui(encoder);
+    }
+}
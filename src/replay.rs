@@ -0,0 +1,323 @@
+use cgmath::vec2;
+use fnv::FnvHasher;
+use image::RgbaImage;
+use std::fmt;
+use std::fs::{self, File};
+use std::hash::Hasher;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use game_state::GameState;
+use input::{InputModel, InputSnapshot};
+
+const MAGIC: &[u8; 4] = b"BELT";
+const VERSION: u32 = 2;
+
+/// magic, version, seed, map hash
+const HEADER_SIZE: usize = 4 + 4 + 8 + 8;
+
+/// aim x, aim y, thrust, strafe, zoom, shoot, auto-brake, physics checksum
+const TICK_SIZE: usize = 5 * 4 + 1 + 1 + 8;
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(io::Error),
+    NotAReplay,
+    UnsupportedVersion(u32),
+    Truncated,
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::Io(error) => write!(f, "{}", error),
+            ReplayError::NotAReplay => write!(f, "not a replay file"),
+            ReplayError::UnsupportedVersion(version) => {
+                write!(f, "unsupported replay version {}", version)
+            }
+            ReplayError::Truncated => write!(f, "replay ends part way through a tick"),
+        }
+    }
+}
+
+/// What happened to the replays being played and recorded while running a tick
+pub struct TickResult {
+    /// False if the replay being played had already ended, so the tick was run with
+    /// `input_model` as it was
+    pub replayed: bool,
+    /// The tick's physics checksum differs from the one in the replay being played
+    pub desynced: bool,
+    pub record_result: Result<(), ReplayError>,
+}
+
+/// Runs `tick` of `game_state`, taking the input from `replay` while it lasts and checking
+/// the result against it, then adds the tick to `recorder`.
+pub fn run_tick(
+    tick: u64,
+    game_state: &mut GameState,
+    input_model: &mut InputModel,
+    replay: Option<&Replay>,
+    recorder: Option<&mut ReplayRecorder>,
+) -> TickResult {
+    let replayed = replay.is_some_and(|replay| replay.apply(tick, input_model));
+    game_state.update(input_model);
+    let checksum = game_state.physics_checksum();
+    let desynced = replay
+        .and_then(|replay| replay.checksum(tick))
+        .is_some_and(|expected| expected != checksum);
+    let record_result = recorder.map_or(Ok(()), |recorder| {
+        recorder.record(&input_model.snapshot(), checksum)
+    });
+    TickResult {
+        replayed,
+        desynced,
+        record_result,
+    }
+}
+
+/// Identifies the contents of a map, so a replay can be checked against the map it's
+/// played back on. Replaying on a different map will almost certainly desync.
+pub fn map_hash(image: &RgbaImage) -> u64 {
+    let mut hasher = FnvHasher::default();
+    hasher.write(&image.width().to_le_bytes());
+    hasher.write(&image.height().to_le_bytes());
+    hasher.write(image);
+    hasher.finish()
+}
+
+/// Writes a replay file as a run progresses, one tick at a time. The file starts with a
+/// header holding the seed and map hash, followed by a fixed-size record per tick of the
+/// input that tick was run with and the resulting physics checksum. All values are
+/// little-endian.
+pub struct ReplayRecorder {
+    writer: BufWriter<File>,
+}
+
+impl ReplayRecorder {
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        seed: u64,
+        map_hash: u64,
+    ) -> Result<Self, ReplayError> {
+        let mut writer = BufWriter::new(File::create(path).map_err(ReplayError::Io)?);
+        let mut header = Vec::with_capacity(HEADER_SIZE);
+        header.extend_from_slice(MAGIC);
+        header.extend_from_slice(&VERSION.to_le_bytes());
+        header.extend_from_slice(&seed.to_le_bytes());
+        header.extend_from_slice(&map_hash.to_le_bytes());
+        writer.write_all(&header).map_err(ReplayError::Io)?;
+        Ok(Self { writer })
+    }
+
+    /// Records that the tick just run was given `input` and left the game with physics
+    /// state hashing to `checksum`.
+    pub fn record(
+        &mut self,
+        input: &InputSnapshot,
+        checksum: u64,
+    ) -> Result<(), ReplayError> {
+        let mut tick = Vec::with_capacity(TICK_SIZE);
+        for value in &[
+            input.aim.x,
+            input.aim.y,
+            input.thrust,
+            input.strafe,
+            input.zoom,
+        ] {
+            tick.extend_from_slice(&value.to_bits().to_le_bytes());
+        }
+        tick.push(input.shoot as u8);
+        tick.push(input.auto_brake as u8);
+        tick.extend_from_slice(&checksum.to_le_bytes());
+        self.writer.write_all(&tick).map_err(ReplayError::Io)
+    }
+
+    pub fn finish(mut self) -> Result<(), ReplayError> {
+        self.writer.flush().map_err(ReplayError::Io)
+    }
+}
+
+struct ReplayTick {
+    input: InputSnapshot,
+    checksum: u64,
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    let mut array = [0; 4];
+    array.copy_from_slice(&bytes[..4]);
+    u32::from_le_bytes(array)
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    let mut array = [0; 8];
+    array.copy_from_slice(&bytes[..8]);
+    u64::from_le_bytes(array)
+}
+
+fn read_f32(bytes: &[u8]) -> f32 {
+    f32::from_bits(read_u32(bytes))
+}
+
+/// A recorded run, which reproduces the original exactly when its inputs are fed to a
+/// `GameState` created with the same seed and map.
+pub struct Replay {
+    seed: u64,
+    map_hash: u64,
+    ticks: Vec<ReplayTick>,
+}
+
+impl Replay {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ReplayError> {
+        let bytes = fs::read(path).map_err(ReplayError::Io)?;
+        if bytes.len() < HEADER_SIZE || &bytes[..4] != MAGIC {
+            return Err(ReplayError::NotAReplay);
+        }
+        let version = read_u32(&bytes[4..]);
+        if version != VERSION {
+            return Err(ReplayError::UnsupportedVersion(version));
+        }
+        let seed = read_u64(&bytes[8..]);
+        let map_hash = read_u64(&bytes[16..]);
+        let body = &bytes[HEADER_SIZE..];
+        if body.len() % TICK_SIZE != 0 {
+            return Err(ReplayError::Truncated);
+        }
+        let ticks = body
+            .chunks(TICK_SIZE)
+            .map(|tick| ReplayTick {
+                input: InputSnapshot {
+                    aim: vec2(read_f32(&tick[0..]), read_f32(&tick[4..])),
+                    thrust: read_f32(&tick[8..]),
+                    strafe: read_f32(&tick[12..]),
+                    zoom: read_f32(&tick[16..]),
+                    shoot: tick[20] != 0,
+                    auto_brake: tick[21] != 0,
+                },
+                checksum: read_u64(&tick[22..]),
+            })
+            .collect();
+        Ok(Self {
+            seed,
+            map_hash,
+            ticks,
+        })
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn map_hash(&self) -> u64 {
+        self.map_hash
+    }
+
+    pub fn num_ticks(&self) -> u64 {
+        self.ticks.len() as u64
+    }
+
+    /// Sets `input_model` to the input recorded for `tick`. Returns false, leaving
+    /// `input_model` unchanged, if the replay ended before `tick`.
+    pub fn apply(&self, tick: u64, input_model: &mut InputModel) -> bool {
+        match self.ticks.get(tick as usize) {
+            Some(replay_tick) => {
+                input_model.progress_buttons();
+                input_model.restore(&replay_tick.input);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The physics checksum recorded after `tick`, if the replay is that long
+    pub fn checksum(&self, tick: u64) -> Option<u64> {
+        self.ticks
+            .get(tick as usize)
+            .map(|replay_tick| replay_tick.checksum)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use map;
+    use sprite_atlas::SpriteAtlas;
+    use std::env;
+    use std::process;
+
+    const NUM_TICKS: u64 = 180;
+    const SEED: u64 = 7;
+
+    fn new_game_state(map_image: &RgbaImage) -> GameState {
+        GameState::new(map_image, &SpriteAtlas::builtin().unwrap(), SEED)
+    }
+
+    /// Flies around and shoots, so the replay covers more than an idle player
+    fn vary_input(tick: u64, input_model: &mut InputModel) {
+        input_model.progress_buttons();
+        match tick {
+            0 => {
+                input_model.set_aim_x(1.);
+                input_model.press_shoot();
+            }
+            40 => input_model.set_thrust(1.),
+            80 => {
+                input_model.set_aim_y(-1.);
+                input_model.release_shoot();
+                input_model.set_strafe(-0.5);
+            }
+            120 => input_model.press_auto_brake(),
+            _ => (),
+        }
+    }
+
+    #[test]
+    fn replay_round_trip() {
+        let path = env::temp_dir().join(format!("belt-replay-test-{}", process::id()));
+        let map_image = map::load_builtin().unwrap();
+
+        let mut recorder =
+            ReplayRecorder::create(&path, SEED, map_hash(&map_image)).unwrap();
+        let mut game_state = new_game_state(&map_image);
+        let mut input_model = InputModel::default();
+        let mut checksums = Vec::new();
+        for tick in 0..NUM_TICKS {
+            vary_input(tick, &mut input_model);
+            let result = run_tick(
+                tick,
+                &mut game_state,
+                &mut input_model,
+                None,
+                Some(&mut recorder),
+            );
+            assert!(result.record_result.is_ok());
+            checksums.push(game_state.physics_checksum());
+        }
+        recorder.finish().unwrap();
+
+        let replay = Replay::load(&path);
+        fs::remove_file(&path).unwrap();
+        let replay = replay.unwrap();
+        assert_eq!(replay.seed(), SEED);
+        assert_eq!(replay.map_hash(), map_hash(&map_image));
+        assert_eq!(replay.num_ticks(), NUM_TICKS);
+
+        let mut game_state = new_game_state(&map_image);
+        let mut input_model = InputModel::default();
+        for (tick, &checksum) in (0..NUM_TICKS).zip(&checksums) {
+            let result =
+                run_tick(tick, &mut game_state, &mut input_model, Some(&replay), None);
+            assert!(result.replayed);
+            assert!(!result.desynced, "desynced at tick {}", tick);
+            assert_eq!(game_state.physics_checksum(), checksum);
+        }
+        let result = run_tick(
+            NUM_TICKS,
+            &mut game_state,
+            &mut input_model,
+            Some(&replay),
+            None,
+        );
+        assert!(!result.replayed);
+        assert!(!result.desynced);
+    }
+}
@@ -6,13 +6,45 @@ in vec2 v_PixelCoord;
 out vec4 Target0;
 uniform sampler2D t_Colour;
 uniform sampler2D t_Visibility;
+uniform sampler2D t_Background;
+uniform sampler2D t_Explored;
 
 uniform Properties {
     vec2 u_EyePositionInPixels;
+    vec2 u_EyeFacing;
+    vec3 u_AmbientLight;
+    float u_FalloffExponent;
+    uint u_NumLights;
+    float u_LightSourceRadiusInPixels;
+    uint u_NumShadowSamples;
+    uint u_VisionConeEnabled;
+    float u_VisionConeHalfAngle;
+    float u_VisionConeSoftEdgeAngle;
+    float u_VisionConeRangeInPixels;
+    float u_VisionConeSoftEdgeInPixels;
+    vec2 u_RegionPositionInPixels;
+    vec2 u_ExploredSizeInPixels;
+    float u_RememberedBrightness;
+    float u_RememberedSaturation;
+};
+
+// Must match MAX_NUM_LIGHTS in the renderer
+const uint MAX_NUM_LIGHTS = 32u;
+
+struct Light {
+    vec2 position_in_pixels;
+    float radius_in_pixels;
+    float intensity;
+    vec3 colour;
+    float padding;
+};
+
+uniform Lights {
+    Light u_Lights[MAX_NUM_LIGHTS];
 };
 
 uniform PropertiesStatic {
-    vec2 u_WindowSizeInPixels;
+    vec2 u_RegionSizeInPixels;
 };
 
 const float JUST_ABOVE_ZERO = 0.01;
@@ -31,6 +63,12 @@ bool is_opaque(vec4 colour) {
     return colour.r < JUST_ABOVE_ZERO;
 }
 
+// Sprites which occlude, such as asteroids, are marked in the blue channel, which the map
+// leaves clear
+bool is_occluder(vec4 colour) {
+    return colour.b > JUST_UNDER_ONE;
+}
+
 struct Lod {
     float exponent;
     float pixel_size;
@@ -43,6 +81,54 @@ const uint EDGE_AXIS_BOTH = 3u;
 const Lod INITIAL_LOD = Lod(4, 16);
 const uint MAX_RAY_TRACE_DEPTH = 1000u;
 
+// Where a ray from `px_start_coord` towards `px_light_coord`, which has got as far as
+// `px_coord`, leaves the cell it's in on a grid of cells `pixel_size` pixels across, and
+// which axis the edge it crosses there is perpendicular to
+vec2 next_cell_edge(
+        vec2 px_coord,
+        vec2 px_start_coord,
+        vec2 px_light_coord,
+        float pixel_size,
+        out uint edge_axis) {
+
+    vec2 px_to_light = px_light_coord - px_start_coord;
+    vec2 scaled_coord = px_coord / pixel_size;
+    vec2 scaled_far_corner = vec2(0, 0);
+    if (px_light_coord.x > px_start_coord.x) {
+        scaled_far_corner.x = floor(scaled_coord.x + 1);
+    } else {
+        scaled_far_corner.x = ceil(scaled_coord.x - 1);
+    }
+    if (px_light_coord.y > px_start_coord.y) {
+        scaled_far_corner.y = floor(scaled_coord.y + 1);
+    } else {
+        scaled_far_corner.y = ceil(scaled_coord.y - 1);
+    }
+    vec2 px_far_corner = scaled_far_corner * pixel_size;
+
+    vec2 px_next_coord = px_coord;
+    if (px_to_light.x == 0) {
+        px_next_coord.y = px_far_corner.y;
+        edge_axis = EDGE_AXIS_Y;
+    } else if (px_to_light.y == 0) {
+        px_next_coord.x = px_far_corner.x;
+        edge_axis = EDGE_AXIS_X;
+    } else {
+        vec2 edge_mult = (px_far_corner - px_coord) / px_to_light;
+        if (edge_mult.x < edge_mult.y) {
+            px_next_coord = vec2(px_far_corner.x, px_coord.y + edge_mult.x * px_to_light.y);
+            edge_axis = EDGE_AXIS_X;
+        } else if (edge_mult.y < edge_mult.x) {
+            px_next_coord = vec2(px_coord.x + edge_mult.y * px_to_light.x, px_far_corner.y);
+            edge_axis = EDGE_AXIS_Y;
+        } else {
+            px_next_coord = px_far_corner;
+            edge_axis = EDGE_AXIS_BOTH;
+        }
+    }
+    return px_next_coord;
+}
+
 bool is_pixel_visible(
         vec2 px_start_coord,
         vec2 px_light_coord,
@@ -52,46 +138,16 @@ bool is_pixel_visible(
 
     float px_start_to_light_distance = distance(px_start_coord, px_light_coord);
     vec2 px_coord = px_start_coord;
-    vec2 px_to_light = px_light_coord - px_coord;
     Lod lod = max_lod;
 
     for (uint i = 0u; i < MAX_RAY_TRACE_DEPTH; i++) {
-        vec2 scaled_coord = px_coord / lod.pixel_size;
-        vec2 scaled_far_corner = vec2(0, 0);
-        if (px_light_coord.x > px_start_coord.x) {
-            scaled_far_corner.x = floor(scaled_coord.x + 1);
-        } else {
-            scaled_far_corner.x = ceil(scaled_coord.x - 1);
-        }
-        if (px_light_coord.y > px_start_coord.y) {
-            scaled_far_corner.y = floor(scaled_coord.y + 1);
-        } else {
-            scaled_far_corner.y = ceil(scaled_coord.y - 1);
-        }
-        vec2 px_far_corner = scaled_far_corner * lod.pixel_size;
-
-
         uint edge_axis = 0u;
-        vec2 px_next_coord = px_coord;
-        if (px_to_light.x == 0) {
-            px_next_coord.y = px_far_corner.y;
-            edge_axis = EDGE_AXIS_Y;
-        } else if (px_to_light.y == 0) {
-            px_next_coord.x = px_far_corner.x;
-            edge_axis = EDGE_AXIS_X;
-        } else {
-            vec2 edge_mult = (px_far_corner - px_coord) / px_to_light;
-            if (edge_mult.x < edge_mult.y) {
-                px_next_coord = vec2(px_far_corner.x, px_coord.y + edge_mult.x * px_to_light.y);
-                edge_axis = EDGE_AXIS_X;
-            } else if (edge_mult.y < edge_mult.x) {
-                px_next_coord = vec2(px_coord.x + edge_mult.y * px_to_light.x, px_far_corner.y);
-                edge_axis = EDGE_AXIS_Y;
-            } else {
-                px_next_coord = px_far_corner;
-                edge_axis = EDGE_AXIS_BOTH;
-            }
-        }
+        vec2 px_next_coord = next_cell_edge(
+                px_coord,
+                px_start_coord,
+                px_light_coord,
+                lod.pixel_size,
+                edge_axis);
 
         vec2 px_sample_coord = (px_coord + px_next_coord) / 2;
         vec4 sample_colour = textureLod(world, px_sample_coord / world_size, lod.exponent);
@@ -140,16 +196,184 @@ bool is_pixel_visible(
     return false;
 }
 
-void main() {
-    if (!is_pixel_visible(
-                v_PixelCoord,
-                u_EyePositionInPixels,
+const uint MAX_OCCLUDER_STEPS = 256u;
+
+// Occluders have jagged edges, which a ray leaving one at a shallow angle can clip again on
+// its way out
+const float OCCLUDER_EDGE_IN_PIXELS = 3.0;
+
+// Follows a ray from a pixel on an occluder towards a light, a pixel at a time, until it's
+// clear of the occluder, and sets `px_left_coord` to where it got to, or to the light if
+// it reached it first. Returns false if anything other than an occluder blocks the ray on
+// the way.
+bool leave_occluder(
+        vec2 px_start_coord,
+        vec2 px_light_coord,
+        sampler2D world,
+        vec2 world_size,
+        out vec2 px_left_coord) {
+
+    float px_start_to_light_distance = distance(px_start_coord, px_light_coord);
+    vec2 px_coord = px_start_coord;
+    float px_left_distance = -1.0;
+
+    for (uint i = 0u; i < MAX_OCCLUDER_STEPS; i++) {
+        uint edge_axis = 0u;
+        vec2 px_next_coord = next_cell_edge(
+                px_coord,
+                px_start_coord,
+                px_light_coord,
+                1,
+                edge_axis);
+
+        vec2 px_sample_coord = (px_coord + px_next_coord) / 2;
+        vec4 sample_colour = textureLod(world, px_sample_coord / world_size, 0);
+        if (!is_occluder(sample_colour)) {
+            if (is_opaque(sample_colour)) {
+                return false;
+            } else if (px_left_distance < 0) {
+                px_left_distance = distance(px_start_coord, px_coord);
+            }
+        }
+        float px_next_distance = distance(px_start_coord, px_next_coord);
+        if (px_next_distance > px_start_to_light_distance) {
+            px_left_coord = px_light_coord;
+            return true;
+        } else if (px_left_distance >= 0 &&
+                px_next_distance - px_left_distance > OCCLUDER_EDGE_IN_PIXELS) {
+            px_left_coord = px_next_coord;
+            return true;
+        }
+        px_coord = px_next_coord;
+    }
+    return false;
+}
+
+// Nothing is known about the world beyond the region, so rays towards lights outside it
+// are traced only as far as where they leave it, keeping clear of the edge so they don't
+// wrap around to the other side
+vec2 clip_to_region(vec2 px_coord, vec2 px_light_coord) {
+    vec2 px_min = vec2(0.5);
+    vec2 px_max = u_RegionSizeInPixels - vec2(0.5);
+    vec2 to_light = px_light_coord - px_coord;
+    float fraction = 1.0;
+    for (int axis = 0; axis < 2; axis++) {
+        if (to_light[axis] > 0) {
+            fraction = min(fraction, (px_max[axis] - px_coord[axis]) / to_light[axis]);
+        } else if (to_light[axis] < 0) {
+            fraction = min(fraction, (px_min[axis] - px_coord[axis]) / to_light[axis]);
+        }
+    }
+    if (fraction >= 1.0) {
+        return px_light_coord;
+    }
+    return px_coord + to_light * max(fraction, 0.0);
+}
+
+// Whether a light, or the eye, can be seen from a pixel. Occluders block rays passing
+// through them but not rays starting on them, or they'd be in their own shadow.
+bool has_line_of_sight(vec2 px_coord, vec2 px_light_coord) {
+    px_light_coord = clip_to_region(px_coord, px_light_coord);
+    if (!is_occluder(textureLod(t_Visibility, px_coord / u_RegionSizeInPixels, 0))) {
+        return is_pixel_visible(
+                px_coord,
+                px_light_coord,
                 t_Visibility,
-                u_WindowSizeInPixels,
-                INITIAL_LOD)) {
-        Target0 = vec4(0,0,0,1);
-    } else {
-        vec4 colour = texture(t_Colour, v_TexCoord);
-        Target0 = colour;
+                u_RegionSizeInPixels,
+                INITIAL_LOD);
+    }
+    vec2 px_left_coord;
+    if (!leave_occluder(
+                px_coord,
+                px_light_coord,
+                t_Visibility,
+                u_RegionSizeInPixels,
+                px_left_coord)) {
+        return false;
+    }
+    return px_left_coord == px_light_coord || is_pixel_visible(
+            px_left_coord,
+            px_light_coord,
+            t_Visibility,
+            u_RegionSizeInPixels,
+            INITIAL_LOD);
+}
+
+// A pseudo-random number between 0 and 1 which differs from pixel to pixel
+float random(vec2 seed) {
+    return fract(sin(dot(seed, vec2(12.9898, 78.233))) * 43758.5453);
+}
+
+// The fraction of a light's disc visible from a pixel, estimated by tracing rays to points
+// spread across the disc, perpendicular to the direction of the light. The points are
+// jittered from pixel to pixel, which turns banding in the penumbra into noise.
+float light_visibility(vec2 px_coord, Light light) {
+    vec2 to_light = light.position_in_pixels - px_coord;
+    if (length(to_light) < 1.0) {
+        return 1.0;
+    }
+    vec2 across = normalize(vec2(-to_light.y, to_light.x)) * u_LightSourceRadiusInPixels;
+    float jitter = random(px_coord);
+    uint num_visible = 0u;
+    for (uint i = 0u; i < u_NumShadowSamples; i++) {
+        float offset = (float(i) + jitter) / float(u_NumShadowSamples) * 2 - 1;
+        vec2 px_sample_light_coord = light.position_in_pixels + across * offset;
+        if (has_line_of_sight(px_coord, px_sample_light_coord)) {
+            num_visible++;
+        }
+    }
+    return float(num_visible) / float(u_NumShadowSamples);
+}
+
+// How much of a pixel the eye can see given the direction it's facing, from 0 outside
+// the vision cone to 1 well inside it. Obstacles aren't taken into account.
+float vision_cone(vec2 px_coord) {
+    if (u_VisionConeEnabled == 0u) {
+        return 1.0;
+    }
+    vec2 eye_to_px = px_coord - u_EyePositionInPixels;
+    float distance_to_px = length(eye_to_px);
+    if (distance_to_px < 1.0) {
+        return 1.0;
+    }
+    float angle = acos(clamp(dot(eye_to_px / distance_to_px, u_EyeFacing), -1.0, 1.0));
+    float within_angle = 1.0 - smoothstep(
+            u_VisionConeHalfAngle - u_VisionConeSoftEdgeAngle,
+            u_VisionConeHalfAngle,
+            angle);
+    float within_range = 1.0 - smoothstep(
+            u_VisionConeRangeInPixels - u_VisionConeSoftEdgeInPixels,
+            u_VisionConeRangeInPixels,
+            distance_to_px);
+    return within_angle * within_range;
+}
+
+// How the map at a pixel is drawn when it's not in view: dimmed and desaturated in
+// proportion to how well it has been seen before, or black if it never has
+vec3 remembered(vec2 px_coord, vec2 tex_coord) {
+    vec2 explored_coord = (u_RegionPositionInPixels + px_coord) / u_ExploredSizeInPixels;
+    float explored = texture(t_Explored, explored_coord).r;
+    vec3 background = texture(t_Background, tex_coord).rgb;
+    vec3 grey = vec3(dot(background, vec3(0.2126, 0.7152, 0.0722)));
+    return mix(grey, background, u_RememberedSaturation) * u_RememberedBrightness * explored;
+}
+
+void main() {
+    vec3 memory = remembered(v_PixelCoord, v_TexCoord);
+    float vision = vision_cone(v_PixelCoord);
+    if (vision == 0 || !has_line_of_sight(v_PixelCoord, u_EyePositionInPixels)) {
+        Target0 = vec4(memory, 1);
+        return;
+    }
+    vec3 light = u_AmbientLight;
+    for (uint i = 0u; i < u_NumLights; i++) {
+        Light l = u_Lights[i];
+        float remaining = 1 - distance(v_PixelCoord, l.position_in_pixels) / l.radius_in_pixels;
+        if (remaining > 0) {
+            float falloff = pow(remaining, u_FalloffExponent);
+            light += l.colour * l.intensity * falloff * light_visibility(v_PixelCoord, l);
+        }
     }
+    vec4 colour = texture(t_Colour, v_TexCoord);
+    Target0 = vec4(mix(memory, colour.rgb * light, vision), colour.a);
 }
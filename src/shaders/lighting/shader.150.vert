@@ -3,7 +3,7 @@
 in vec2 a_CornerZeroToOne;
 
 uniform PropertiesStatic {
-    vec2 u_WindowSizeInPixels;
+    vec2 u_RegionSizeInPixels;
 };
 
 out vec2 v_TexCoord;
@@ -15,7 +15,7 @@ void main() {
         1 - a_CornerZeroToOne.y * 2);
 
     v_TexCoord = a_CornerZeroToOne;
-    v_PixelCoord = a_CornerZeroToOne * u_WindowSizeInPixels;
+    v_PixelCoord = a_CornerZeroToOne * u_RegionSizeInPixels;
 
     gl_Position = vec4(screen_coord, 0, 1);
 }
@@ -4,14 +4,15 @@ in vec2 a_CornerZeroToOne;
 
 uniform Properties {
     vec2 u_OutputSizeInPixels;
+    vec2 u_TilePositionInPixels;
+    vec2 u_TileSizeInPixels;
 };
 
 out vec2 v_TexCoord;
 
 void main() {
-    vec2 screen_coord = vec2(
-        a_CornerZeroToOne.x * 2 - 1,
-        a_CornerZeroToOne.y * 2 - 1);
+    vec2 pixel_coord = u_TilePositionInPixels + a_CornerZeroToOne * u_TileSizeInPixels;
+    vec2 screen_coord = pixel_coord / u_OutputSizeInPixels * 2 - 1;
 
     v_TexCoord = a_CornerZeroToOne;
 
@@ -3,7 +3,7 @@
 in vec2 a_CornerZeroToOne;
 
 uniform Properties {
-    vec2 u_PlayerPositionInPixels;
+    vec2 u_CameraPositionInPixels;
     float u_Zoom;
 };
 uniform PropertiesStatic {
@@ -15,8 +15,8 @@ out vec2 v_TexCoord;
 
 void main() {
 
-    vec2 player_position = vec2(u_PlayerPositionInPixels.x, u_WindowSizeInPixels.y - u_PlayerPositionInPixels.y);
-    vec2 top_left_corner = player_position - u_WindowSizeInPixels / (u_Zoom * 2);
+    vec2 camera_position = vec2(u_CameraPositionInPixels.x, u_InputSizeInPixels.y - u_CameraPositionInPixels.y);
+    vec2 top_left_corner = camera_position - u_WindowSizeInPixels / (u_Zoom * 2);
     vec2 corner = top_left_corner + (u_WindowSizeInPixels / u_Zoom) * a_CornerZeroToOne;
     v_TexCoord = corner / u_InputSizeInPixels;
 
@@ -0,0 +1,9 @@
+#version 150 core
+
+in vec2 v_SpriteSheetSampleCoord;
+out vec4 TargetColour;
+uniform sampler2D t_SpriteSheet;
+
+void main() {
+    TargetColour = texture(t_SpriteSheet, v_SpriteSheetSampleCoord);
+}
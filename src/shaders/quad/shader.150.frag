@@ -1,14 +1,28 @@
 #version 150 core
 
 in vec2 v_SpriteSheetSampleCoord;
-flat in uint v_IsPlayer;
+flat in uint v_Occludes;
 out vec4 TargetColour;
 out vec4 TargetVisibility;
 uniform sampler2D t_SpriteSheet;
 
+// Sprites are blended into the targets, so a transparent output leaves a target unchanged
+const vec4 UNCHANGED = vec4(0, 0, 0, 0);
+
+// The lighting shader treats anything partially opaque as needing a closer look, all the
+// way down to individual pixels, so occluders are either fully opaque or not there at all
+const float OCCLUDER_ALPHA_THRESHOLD = 0.5;
+
+// Opaque, and marked in the blue channel so the lighting shader can tell occluders apart
+// from walls and let them be seen and lit even though they block what's behind them
+const vec4 OCCLUDER = vec4(0, 0, 1, 1);
+
 void main() {
-    if (v_IsPlayer == 0u) {
-        vec4 sprite_sheet_sample_colour = texture(t_SpriteSheet, v_SpriteSheetSampleCoord);
-        TargetColour = sprite_sheet_sample_colour;
+    vec4 sprite_sheet_sample_colour = texture(t_SpriteSheet, v_SpriteSheetSampleCoord);
+    TargetColour = sprite_sheet_sample_colour;
+    if (v_Occludes != 0u && sprite_sheet_sample_colour.a >= OCCLUDER_ALPHA_THRESHOLD) {
+        TargetVisibility = OCCLUDER;
+    } else {
+        TargetVisibility = UNCHANGED;
     }
 }
@@ -6,16 +6,18 @@ in vec2 i_DimensionsInPixels;
 in vec2 i_FacingVector;
 in vec2 i_SpritePositionOfTopLeftInPixels;
 in vec2 i_SpriteDimensionsInPixels;
-in float i_IsPlayer;
+in float i_Occludes;
 
 uniform Properties {
-    vec2 u_WindowSizeInPixels;
+    vec2 u_RegionPositionOfTopLeftInPixels;
+    vec2 u_RegionSizeInPixels;
     vec2 u_SpriteSheetSizeInPixels;
     float u_SpriteScale;
+    uint u_FlipVertically;
 };
 
 out vec2 v_SpriteSheetSampleCoord;
-flat out uint v_IsPlayer;
+flat out uint v_Occludes;
 
 void main() {
 
@@ -26,17 +28,21 @@ void main() {
     vec2 rotated_pixel_offset_from_centre =
         pixel_offset_from_centre.y * i_FacingVector -
         pixel_offset_from_centre.x * right_facing_vector;
-    vec2 pixel_coord = i_PositionOfCentreInPixels + rotated_pixel_offset_from_centre;
+    vec2 pixel_coord = i_PositionOfCentreInPixels + rotated_pixel_offset_from_centre
+        - u_RegionPositionOfTopLeftInPixels;
 
     vec2 screen_coord = vec2(
-        pixel_coord.x / u_WindowSizeInPixels.x * 2 - 1,
-        pixel_coord.y / u_WindowSizeInPixels.y * 2 - 1);
+        pixel_coord.x / u_RegionSizeInPixels.x * 2 - 1,
+        pixel_coord.y / u_RegionSizeInPixels.y * 2 - 1);
+    if (u_FlipVertically != 0u) {
+        screen_coord.y = -screen_coord.y;
+    }
 
     v_SpriteSheetSampleCoord =
         u_SpriteScale * (i_SpritePositionOfTopLeftInPixels +
         i_SpriteDimensionsInPixels * a_CornerZeroToOne) / u_SpriteSheetSizeInPixels;
 
-    v_IsPlayer = uint(i_IsPlayer);
+    v_Occludes = uint(i_Occludes);
 
     gl_Position = vec4(screen_coord, 0, 1);
 }
@@ -0,0 +1,160 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use toml;
+
+const BUILTIN: &str = include_str!("images/sprites.toml");
+
+/// A rectangle of the sprite sheet
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct SpriteRect {
+    #[serde(rename = "position")]
+    pub position_of_top_left_in_pixels: [f32; 2],
+    #[serde(rename = "size")]
+    pub dimensions_in_pixels: [f32; 2],
+}
+
+/// A sequence of sprites shown one after another, each for the same length of time
+#[derive(Debug, Clone)]
+pub struct Animation {
+    pub frames: Vec<SpriteRect>,
+    pub frame_seconds: f32,
+    pub looping: bool,
+}
+
+impl Animation {
+    /// The frame to show `seconds` after the animation started, or `None` once an animation
+    /// which doesn't loop has finished.
+    pub fn frame(&self, seconds: f32) -> Option<SpriteRect> {
+        let index = (seconds / self.frame_seconds) as usize;
+        if self.looping {
+            Some(self.frames[index % self.frames.len()])
+        } else {
+            self.frames.get(index).cloned()
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SpriteAtlasError {
+    Parse(toml::de::Error),
+    UnknownSprite { animation: String, sprite: String },
+    NoFrames(String),
+    NonPositiveFrameSeconds(String),
+}
+
+impl fmt::Display for SpriteAtlasError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SpriteAtlasError::Parse(error) => write!(f, "{}", error),
+            SpriteAtlasError::UnknownSprite { animation, sprite } => write!(
+                f,
+                "animation \"{}\" uses unknown sprite \"{}\"",
+                animation, sprite
+            ),
+            SpriteAtlasError::NoFrames(animation) => {
+                write!(f, "animation \"{}\" has no frames", animation)
+            }
+            SpriteAtlasError::NonPositiveFrameSeconds(animation) => write!(
+                f,
+                "animation \"{}\" must show each frame for more than 0 seconds",
+                animation
+            ),
+        }
+    }
+}
+
+/// The format of the atlas manifest, before sprite names are resolved
+#[derive(Deserialize)]
+struct SpriteAtlasFile {
+    sprites: BTreeMap<String, SpriteRect>,
+    #[serde(default)]
+    animations: BTreeMap<String, AnimationFile>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct AnimationFile {
+    frames: Vec<String>,
+    frame_seconds: f32,
+    #[serde(default)]
+    looping: bool,
+}
+
+/// Names the sprites and animations in the sprite sheet.
+pub struct SpriteAtlas {
+    sprites: BTreeMap<String, SpriteRect>,
+    animations: BTreeMap<String, Animation>,
+}
+
+impl SpriteAtlas {
+    pub fn parse(contents: &str) -> Result<Self, SpriteAtlasError> {
+        let file: SpriteAtlasFile =
+            toml::from_str(contents).map_err(SpriteAtlasError::Parse)?;
+        let sprites = file.sprites;
+        let mut animations = BTreeMap::new();
+        for (name, animation) in file.animations {
+            if animation.frames.is_empty() {
+                return Err(SpriteAtlasError::NoFrames(name));
+            }
+            if animation.frame_seconds <= 0. {
+                return Err(SpriteAtlasError::NonPositiveFrameSeconds(name));
+            }
+            let frames = animation
+                .frames
+                .iter()
+                .map(|sprite| {
+                    sprites.get(sprite).cloned().ok_or_else(|| {
+                        SpriteAtlasError::UnknownSprite {
+                            animation: name.clone(),
+                            sprite: sprite.clone(),
+                        }
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            animations.insert(
+                name,
+                Animation {
+                    frames,
+                    frame_seconds: animation.frame_seconds,
+                    looping: animation.looping,
+                },
+            );
+        }
+        Ok(Self {
+            sprites,
+            animations,
+        })
+    }
+
+    /// The atlas of the sprite sheet built into the game
+    pub fn builtin() -> Result<Self, SpriteAtlasError> {
+        Self::parse(BUILTIN)
+    }
+
+    pub fn sprite(&self, name: &str) -> Option<SpriteRect> {
+        self.sprites.get(name).cloned()
+    }
+
+    pub fn animation(&self, name: &str) -> Option<&Animation> {
+        self.animations.get(name)
+    }
+
+    /// The size of the smallest part of the sprite sheet, starting at its top-left corner,
+    /// which contains every sprite
+    pub fn size_in_pixels(&self) -> [u32; 2] {
+        self.sprites.values().fold([0, 0], |size, sprite| {
+            [
+                size[0].max(
+                    (sprite.position_of_top_left_in_pixels[0]
+                        + sprite.dimensions_in_pixels[0])
+                        .ceil() as u32,
+                ),
+                size[1].max(
+                    (sprite.position_of_top_left_in_pixels[1]
+                        + sprite.dimensions_in_pixels[1])
+                        .ceil() as u32,
+                ),
+            ]
+        })
+    }
+}
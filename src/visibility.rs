@@ -0,0 +1,414 @@
+use cgmath::{InnerSpace, Vector2, vec2};
+use image::{Rgba, RgbaImage};
+
+const JUST_ABOVE_ZERO: f32 = 0.01;
+const JUST_UNDER_ONE: f32 = 0.99;
+
+const INITIAL_LOD: Lod = Lod {
+    exponent: 4.,
+    pixel_size: 16.,
+};
+const MAX_RAY_TRACE_DEPTH: u32 = 1000;
+
+fn srgb_to_linear(value: u8) -> f32 {
+    let value = value as f32 / 255.;
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> u8 {
+    let value = if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1. / 2.4) - 0.055
+    };
+    (value * 255.).round().clamp(0., 255.) as u8
+}
+
+/// Decides which map pixels are see-through, and therefore passable, using the same rule
+/// as the map shader. The map texture is sRGB, so the shader compares linear values.
+pub fn is_transparent(pixel: &Rgba<u8>) -> bool {
+    pixel.data[..3]
+        .iter()
+        .all(|&c| srgb_to_linear(c) > JUST_UNDER_ONE)
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    if edge1 <= edge0 {
+        return if x < edge0 { 0. } else { 1. };
+    }
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0., 1.);
+    t * t * (3. - 2. * t)
+}
+
+fn is_roughly_integer(f: f32) -> bool {
+    let f = f - f.floor();
+    !(JUST_ABOVE_ZERO..=JUST_UNDER_ONE).contains(&f)
+}
+
+#[derive(Clone, Copy)]
+enum EdgeAxis {
+    X,
+    Y,
+    Both,
+}
+
+#[derive(Clone, Copy)]
+struct Lod {
+    exponent: f32,
+    pixel_size: f32,
+}
+
+struct Level {
+    width: u32,
+    height: u32,
+    /// sRGB-encoded visibility, as stored in the GPU texture
+    cells: Vec<u8>,
+}
+
+impl Level {
+    fn get(&self, x: u32, y: u32) -> u8 {
+        self.cells[(y * self.width + x) as usize]
+    }
+
+    /// Anything beyond the level's edges is opaque, as it is outside the map on the GPU
+    fn get_or_opaque(&self, x: u32, y: u32) -> u8 {
+        if x < self.width && y < self.height {
+            self.get(x, y)
+        } else {
+            0
+        }
+    }
+
+    /// Halves the size of the level, rounding up, so that each cell of the result covers
+    /// the same pixels as the GPU's cells at the next level. An odd last row or column is
+    /// averaged with the opaque pixels beyond the edge of the level.
+    fn downsample(&self) -> Self {
+        let width = self.width.div_ceil(2);
+        let height = self.height.div_ceil(2);
+        let mut cells = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let (x0, y0) = (x * 2, y * 2);
+                let total = srgb_to_linear(self.get(x0, y0))
+                    + srgb_to_linear(self.get_or_opaque(x0 + 1, y0))
+                    + srgb_to_linear(self.get_or_opaque(x0, y0 + 1))
+                    + srgb_to_linear(self.get_or_opaque(x0 + 1, y0 + 1));
+                cells.push(linear_to_srgb(total / 4.));
+            }
+        }
+        Self {
+            width,
+            height,
+            cells,
+        }
+    }
+}
+
+/// Limits what can be seen from a point to a cone centred on the direction it's facing.
+/// Both its sides and its far end fade out gradually rather than stopping abruptly.
+#[derive(Debug, Clone, Copy)]
+pub struct VisionCone {
+    /// Angle between the centre of the cone and either side, in radians
+    pub half_angle: f32,
+    pub range_in_pixels: f32,
+    /// Width of the fade at the sides of the cone, in radians
+    pub soft_edge_angle: f32,
+    /// Width of the fade at the far end of the cone
+    pub soft_edge_in_pixels: f32,
+}
+
+impl VisionCone {
+    /// How much of `position` an eye at `eye` looking along `facing` can see, from 0
+    /// outside the cone to 1 well inside it. Obstacles aren't taken into account. This is
+    /// a port of `vision_cone` in the lighting shader.
+    pub fn visibility(
+        &self,
+        eye: Vector2<f32>,
+        facing: Vector2<f32>,
+        position: Vector2<f32>,
+    ) -> f32 {
+        let eye_to_position = position - eye;
+        let distance = eye_to_position.magnitude();
+        if distance < 1. {
+            return 1.;
+        }
+        let angle = (eye_to_position / distance)
+            .dot(facing)
+            .clamp(-1., 1.)
+            .acos();
+        let within_angle = 1.
+            - smoothstep(
+                self.half_angle - self.soft_edge_angle,
+                self.half_angle,
+                angle,
+            );
+        let within_range = 1.
+            - smoothstep(
+                self.range_in_pixels - self.soft_edge_in_pixels,
+                self.range_in_pixels,
+                distance,
+            );
+        within_angle * within_range
+    }
+}
+
+/// CPU-side copy of the mip-mapped visibility texture which the lighting shader ray-marches
+/// through. Each level is built from the one above it by averaging 2x2 blocks in linear
+/// space and storing the result as 8-bit sRGB, the way `glGenerateMipmap` treats an sRGB
+/// texture, so partially-opaque regions are classified exactly as they are on the GPU.
+/// Entities are not included.
+pub struct VisibilityMap {
+    levels: Vec<Level>,
+}
+
+impl VisibilityMap {
+    pub fn from_image(image: &RgbaImage) -> Self {
+        let (width, height) = image.dimensions();
+        let cells = image
+            .pixels()
+            .map(|pixel| if is_transparent(pixel) { 255 } else { 0 })
+            .collect();
+        let mut levels = vec![Level {
+            width,
+            height,
+            cells,
+        }];
+        while levels[levels.len() - 1].width > 1 || levels[levels.len() - 1].height > 1 {
+            let next = levels[levels.len() - 1].downsample();
+            levels.push(next);
+        }
+        Self { levels }
+    }
+
+    pub fn size(&self) -> Vector2<f32> {
+        vec2(self.levels[0].width as f32, self.levels[0].height as f32)
+    }
+
+    /// Equivalent to `textureLod` with nearest-neighbour filtering and repeat wrapping. Each
+    /// cell of a level covers 2^level pixels, as it does in the GPU's region, which is
+    /// aligned so that holds even where the map's size isn't a power of two.
+    fn sample(&self, px_coord: Vector2<f32>, exponent: f32) -> f32 {
+        let max_level = self.levels.len() - 1;
+        let level_index = (exponent.max(0.) as usize).min(max_level);
+        let level = &self.levels[level_index];
+        let cell_size = (1u64 << level_index) as f32;
+        let texel = |coord: f32, level_size: u32| {
+            ((coord / cell_size).floor() as i64).rem_euclid(level_size as i64) as u32
+        };
+        let x = texel(px_coord.x, level.width);
+        let y = texel(px_coord.y, level.height);
+        srgb_to_linear(level.get(x, y))
+    }
+
+    /// Returns true if nothing opaque lies on the straight line from `start` to `light`.
+    /// This is a port of `is_pixel_visible` in the lighting shader and gives the same
+    /// answer for the same inputs.
+    pub fn is_visible(&self, start: Vector2<f32>, light: Vector2<f32>) -> bool {
+        let max_lod = INITIAL_LOD;
+        let start_to_light_distance = (light - start).magnitude();
+        let mut px_coord = start;
+        let px_to_light = light - px_coord;
+        let mut lod = max_lod;
+
+        for _ in 0..MAX_RAY_TRACE_DEPTH {
+            let scaled_coord = px_coord / lod.pixel_size;
+            let scaled_far_corner = vec2(
+                if light.x > start.x {
+                    (scaled_coord.x + 1.).floor()
+                } else {
+                    (scaled_coord.x - 1.).ceil()
+                },
+                if light.y > start.y {
+                    (scaled_coord.y + 1.).floor()
+                } else {
+                    (scaled_coord.y - 1.).ceil()
+                },
+            );
+            let px_far_corner = scaled_far_corner * lod.pixel_size;
+
+            let (px_next_coord, edge_axis) = if px_to_light.x == 0. {
+                (vec2(px_coord.x, px_far_corner.y), EdgeAxis::Y)
+            } else if px_to_light.y == 0. {
+                (vec2(px_far_corner.x, px_coord.y), EdgeAxis::X)
+            } else {
+                let edge_mult = vec2(
+                    (px_far_corner.x - px_coord.x) / px_to_light.x,
+                    (px_far_corner.y - px_coord.y) / px_to_light.y,
+                );
+                if edge_mult.x < edge_mult.y {
+                    (
+                        vec2(px_far_corner.x, px_coord.y + edge_mult.x * px_to_light.y),
+                        EdgeAxis::X,
+                    )
+                } else if edge_mult.y < edge_mult.x {
+                    (
+                        vec2(px_coord.x + edge_mult.y * px_to_light.x, px_far_corner.y),
+                        EdgeAxis::Y,
+                    )
+                } else {
+                    (px_far_corner, EdgeAxis::Both)
+                }
+            };
+
+            let sample = self.sample((px_coord + px_next_coord) / 2., lod.exponent);
+
+            if sample < JUST_ABOVE_ZERO {
+                return false;
+            } else if sample > JUST_UNDER_ONE {
+                if (px_next_coord - start).magnitude() > start_to_light_distance {
+                    return true;
+                }
+                px_coord = px_next_coord;
+                while lod.exponent < max_lod.exponent {
+                    let next_pixel_size = lod.pixel_size * 2.;
+                    let aligned = match edge_axis {
+                        EdgeAxis::X => is_roughly_integer(px_coord.x / next_pixel_size),
+                        EdgeAxis::Y => is_roughly_integer(px_coord.y / next_pixel_size),
+                        EdgeAxis::Both => {
+                            is_roughly_integer(px_coord.x / next_pixel_size)
+                                && is_roughly_integer(px_coord.y / next_pixel_size)
+                        }
+                    };
+                    if !aligned {
+                        break;
+                    }
+                    lod.exponent += 1.;
+                    lod.pixel_size = next_pixel_size;
+                }
+            } else {
+                lod.exponent -= 1.;
+                lod.pixel_size /= 2.;
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAP_SIZE: u32 = 64;
+
+    /// Cells less than 1% opaque count as see-through, so a single pixel on its own
+    /// wouldn't be enough to make a 16 pixel cell partially opaque
+    const BLOCK: [(u32, u32); 4] = [(40, 40), (41, 40), (40, 41), (41, 41)];
+
+    /// A see-through map with the given pixels made opaque
+    fn map_with_opaque_pixels(opaque_pixels: &[(u32, u32)]) -> VisibilityMap {
+        sized_map_with_opaque_pixels(MAP_SIZE, opaque_pixels)
+    }
+
+    fn sized_map_with_opaque_pixels(
+        size: u32,
+        opaque_pixels: &[(u32, u32)],
+    ) -> VisibilityMap {
+        let mut image = RgbaImage::from_pixel(size, size, Rgba([255, 255, 255, 255]));
+        for &(x, y) in opaque_pixels {
+            image.put_pixel(x, y, Rgba([0, 0, 0, 255]));
+        }
+        VisibilityMap::from_image(&image)
+    }
+
+    /// Checks both directions along the ray, which take different paths through the code
+    fn assert_visibility(
+        map: &VisibilityMap,
+        a: Vector2<f32>,
+        b: Vector2<f32>,
+        visible: bool,
+    ) {
+        assert_eq!(map.is_visible(a, b), visible, "from {:?} to {:?}", a, b);
+        assert_eq!(map.is_visible(b, a), visible, "from {:?} to {:?}", b, a);
+    }
+
+    #[test]
+    fn clear_line_of_sight() {
+        let map = map_with_opaque_pixels(&[]);
+        assert_visibility(&map, vec2(8.5, 8.5), vec2(50.5, 40.5), true);
+        assert_visibility(&map, vec2(3.5, 60.5), vec2(61.5, 2.5), true);
+    }
+
+    #[test]
+    fn wall_blocks_line_of_sight() {
+        let wall = (0..MAP_SIZE).map(|y| (32, y)).collect::<Vec<_>>();
+        let map = map_with_opaque_pixels(&wall);
+        assert_visibility(&map, vec2(10.5, 20.5), vec2(50.5, 30.5), false);
+        assert_visibility(&map, vec2(10.5, 20.5), vec2(30.5, 50.5), true);
+    }
+
+    #[test]
+    fn partially_opaque_cell_is_checked_pixel_by_pixel() {
+        // Rays through the 16 pixel cell containing the block have to look at individual
+        // pixels
+        let map = map_with_opaque_pixels(&BLOCK);
+        assert_visibility(&map, vec2(20.5, 36.5), vec2(60.5, 44.5), false);
+        assert_visibility(&map, vec2(20.5, 34.5), vec2(60.5, 36.5), true);
+    }
+
+    #[test]
+    fn horizontal_rays() {
+        let map = map_with_opaque_pixels(&BLOCK);
+        assert_visibility(&map, vec2(10.5, 40.5), vec2(60.5, 40.5), false);
+        assert_visibility(&map, vec2(10.5, 42.5), vec2(60.5, 42.5), true);
+        assert_visibility(&map, vec2(10.5, 40.5), vec2(39.5, 40.5), true);
+    }
+
+    #[test]
+    fn vertical_rays() {
+        let map = map_with_opaque_pixels(&BLOCK);
+        assert_visibility(&map, vec2(40.5, 10.5), vec2(40.5, 60.5), false);
+        assert_visibility(&map, vec2(42.5, 10.5), vec2(42.5, 60.5), true);
+        assert_visibility(&map, vec2(40.5, 10.5), vec2(40.5, 39.5), true);
+    }
+
+    #[test]
+    fn cells_line_up_with_the_gpu_when_the_size_is_not_a_multiple_of_16() {
+        // The GPU samples a region aligned to 16 pixel cells, so the map's cells must be
+        // too, even though the map doesn't divide into them evenly
+        let block = (976..980)
+            .flat_map(|x| (498..502).map(move |y| (x, y)))
+            .collect::<Vec<_>>();
+        let map = sized_map_with_opaque_pixels(1000, &block);
+        assert_visibility(&map, vec2(977.5, 100.5), vec2(977.5, 900.5), false);
+        assert_visibility(&map, vec2(970.5, 100.5), vec2(970.5, 900.5), true);
+        assert_visibility(&map, vec2(100.5, 499.5), vec2(990.5, 499.5), false);
+    }
+
+    #[test]
+    fn downsampling_averages_in_linear_space() {
+        let level = Level {
+            width: 2,
+            height: 2,
+            cells: vec![255, 255, 0, 0],
+        };
+        let downsampled = level.downsample();
+        assert_eq!((downsampled.width, downsampled.height), (1, 1));
+        // Half of full brightness in linear space, which is much more than half in sRGB
+        assert_eq!(downsampled.get(0, 0), linear_to_srgb(0.5));
+        assert_eq!(downsampled.get(0, 0), 188);
+
+        let level = Level {
+            width: 2,
+            height: 2,
+            cells: vec![255, 0, 0, 0],
+        };
+        assert_eq!(level.downsample().get(0, 0), 137);
+    }
+
+    #[test]
+    fn downsampling_odd_sizes_pads_with_opaque_cells() {
+        let level = Level {
+            width: 3,
+            height: 2,
+            cells: vec![255, 255, 255, 255, 255, 255],
+        };
+        let downsampled = level.downsample();
+        assert_eq!((downsampled.width, downsampled.height), (2, 1));
+        assert_eq!(downsampled.get(0, 0), 255);
+        assert_eq!(downsampled.get(1, 0), linear_to_srgb(0.5));
+    }
+}